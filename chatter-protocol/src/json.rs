@@ -0,0 +1,36 @@
+//! Config and job params carried in a message.
+//!
+//! The config types are shaped for the config file: flattened structs, internally tagged and
+//! untagged enums, fields skipped when empty and arbitrary JSON values. Binary codecs like
+//! bincode can't carry those, so with them the value travels as its JSON document instead.
+
+use serde::{
+    de::DeserializeOwned, de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize,
+    Serializer,
+};
+
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        value.serialize(serializer)
+    } else {
+        let json = serde_json::to_string(value).map_err(S::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        T::deserialize(deserializer)
+    } else {
+        let json = String::deserialize(deserializer)?;
+        serde_json::from_str(&json).map_err(D::Error::custom)
+    }
+}
@@ -1,11 +1,20 @@
 use config::{GeneralConfig, GeneralConfigDiff};
+use serde_json::Value;
+use url::Url;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+mod json;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub enum ChatterMessage {
     Hello {
+        #[serde(with = "json")]
         config: GeneralConfig,
         priority: u32,
         connected: Vec<String>,
+        /// Hash of the plugin lockfile, if the node has one
+        lock_hash: Option<String>,
+        /// URL to dial the node at instead of its address in the config
+        advertise: Option<Url>,
     },
     QueueUpdate {
         length: u32,
@@ -13,8 +22,24 @@ pub enum ChatterMessage {
     NodeConfigUpdate {
         priority: u32,
     },
-    GeneralConfigUpdate(GeneralConfigDiff),
-    // SendTask {},
+    GeneralConfigUpdate(#[serde(with = "json")] GeneralConfigDiff),
+    /// An occurrence of a schedule was fired, as a unix timestamp
+    ScheduleFired {
+        schedule: String,
+        at: i64,
+    },
+    /// A job placed on the receiving node, with its params in the order the task expects them
+    SendTask {
+        id: String,
+        task: String,
+        #[serde(with = "json")]
+        params: Vec<Value>,
+    },
+    /// Answer to [`Self::SendTask`], with why the job was refused if it was
+    TaskAccepted {
+        id: String,
+        error: Option<String>,
+    },
     // SendTaskResult {}
     Ping(u32),
     Pong(u32),
@@ -0,0 +1,126 @@
+use chatter_protocol::ChatterMessage;
+use config::GeneralConfig;
+use diff::Diff;
+use futures_util::{SinkExt, StreamExt};
+use secure_comms::{Codec, DataStream, StreamConfig};
+use serde_json::json;
+use tokio::io::DuplexStream;
+
+fn general(retries: u32) -> GeneralConfig {
+    serde_json::from_value(json!({
+        "nodes": [{"address": "https://node1.example.com", "name": "node1"}],
+        "tasks": {
+            "job1": {
+                "params": [
+                    {"name": "x", "type": "number", "minimum": 0.5},
+                    {"name": "mode", "type": "enum", "values": ["a", 1], "default": "a"}
+                ],
+                "script": {"source": "git", "repo": "https://example.com/jobs.git"},
+                "max_retries": retries,
+                "backoff": {"type": "exponential", "initial": 2},
+                "secrets": {"TOKEN": {"source": "env", "var": "TOKEN"}}
+            },
+            "job2": {"params": [], "script": "job2.ts"}
+        },
+        "plugins": ["plugin1", {"name": "plugin2", "version": "1.0.0"}],
+        "schedules": {
+            "nightly": {"task": "job1", "cron": "0 3 * * *", "params": {"x": 1}}
+        }
+    }))
+    .unwrap()
+}
+
+async fn pair(
+    codec: Codec,
+) -> (
+    DataStream<DuplexStream, ChatterMessage>,
+    DataStream<DuplexStream, ChatterMessage>,
+) {
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let config = StreamConfig {
+        codecs: vec![codec],
+        ..StreamConfig::default()
+    };
+    let (x, y) = tokio::join!(
+        DataStream::negotiate(x, &config),
+        DataStream::negotiate(y, &config)
+    );
+    (x.unwrap(), y.unwrap())
+}
+
+#[tokio::test]
+async fn config_with_tasks() {
+    let config = general(0);
+    let diff = config.diff(&general(3));
+    for codec in [Codec::Bincode, Codec::MessagePack, Codec::Cbor, Codec::Json] {
+        let (mut a, mut b) = pair(codec).await;
+        a.send(ChatterMessage::Hello {
+            config: config.clone(),
+            priority: 1,
+            connected: vec![],
+            lock_hash: None,
+            advertise: None,
+        })
+        .await
+        .unwrap();
+        let Some(Ok(ChatterMessage::Hello {
+            config: received, ..
+        })) = b.next().await
+        else {
+            panic!("no Hello with {codec:?}");
+        };
+        assert_eq!(received, config);
+
+        a.send(ChatterMessage::GeneralConfigUpdate(diff.clone()))
+            .await
+            .unwrap();
+        let Some(Ok(ChatterMessage::GeneralConfigUpdate(received))) = b.next().await else {
+            panic!("no update with {codec:?}");
+        };
+        let mut updated = config.clone();
+        updated.apply(&received);
+        assert_eq!(updated, general(3));
+
+        let params = vec![json!(3), json!("x"), json!({"nested": [null, 1.5]})];
+        a.send(ChatterMessage::SendTask {
+            id: "1".into(),
+            task: "job1".into(),
+            params: params.clone(),
+        })
+        .await
+        .unwrap();
+        let Some(Ok(ChatterMessage::SendTask {
+            params: received, ..
+        })) = b.next().await
+        else {
+            panic!("no job with {codec:?}");
+        };
+        assert_eq!(received, params);
+    }
+}
+
+#[tokio::test]
+async fn default_codec() {
+    let config = general(0);
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let stream_config = StreamConfig::default();
+    let (a, b) = tokio::join!(
+        DataStream::negotiate(x, &stream_config),
+        DataStream::negotiate(y, &stream_config)
+    );
+    let (mut a, mut b): (DataStream<_, ChatterMessage>, DataStream<_, ChatterMessage>) =
+        (a.unwrap(), b.unwrap());
+    a.send(ChatterMessage::Hello {
+        config: config.clone(),
+        priority: 1,
+        connected: vec!["node2".to_string()],
+        lock_hash: Some("abc".to_string()),
+        advertise: None,
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        b.next().await,
+        Some(Ok(ChatterMessage::Hello { config: received, .. })) if received == config
+    ));
+}
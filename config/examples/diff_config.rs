@@ -7,6 +7,5 @@ fn main() {
     let config2 =
         serde_json::from_str::<GeneralConfig>(include_str!("config2.example.json")).unwrap();
     let diff = config1.diff(&config2);
-    let diff_json = serde_json::to_string_pretty(&diff).unwrap();
-    println!("{diff_json}")
+    println!("{}", config1.diff_changes(&diff))
 }
@@ -0,0 +1,304 @@
+use std::{collections::HashMap, fmt::Display};
+
+use diff::Diff;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::{GeneralConfig, GeneralConfigDiff};
+
+/// Part of the general config whose entries are compared by name
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    Node,
+    Task,
+    Plugin,
+    Schedule,
+    Repository,
+    /// Any other field of the general config, by field name
+    Setting,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// Top level fields of the entry that changed, empty if the entry isn't an object
+    Modified { fields: Vec<String> },
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+pub struct Change {
+    pub section: Section,
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+}
+
+/// Changes between two general configs, ordered by section and name
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct Changes(pub Vec<Change>);
+
+/// Entry changed in different ways by both sides of a merge
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+pub struct Conflict {
+    pub section: Section,
+    pub name: String,
+    /// Entry as left by each side, `None` if it was removed
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{} conflicting changes: {}", .0.len(), .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct MergeConflicts(pub Vec<Conflict>);
+
+type Entries = Vec<(String, Value)>;
+
+impl Section {
+    const ALL: [Self; 6] = [
+        Self::Node,
+        Self::Task,
+        Self::Plugin,
+        Self::Schedule,
+        Self::Repository,
+        Self::Setting,
+    ];
+
+    /// Field of [`GeneralConfig`] holding the section, `None` for settings
+    const fn field(self) -> Option<&'static str> {
+        match self {
+            Self::Node => Some("nodes"),
+            Self::Task => Some("tasks"),
+            Self::Plugin => Some("plugins"),
+            Self::Schedule => Some("schedules"),
+            Self::Repository => Some("repositories"),
+            Self::Setting => None,
+        }
+    }
+
+    const fn is_list(self) -> bool {
+        matches!(self, Self::Node | Self::Plugin)
+    }
+
+    /// Entries of the section, in the order of the config for lists and by name for maps
+    fn entries(self, config: &GeneralConfig) -> Entries {
+        fn sorted<T: Serialize>(map: &HashMap<String, T>) -> Entries {
+            let mut entries = map
+                .iter()
+                .map(|(name, v)| (name.clone(), to_value(v)))
+                .collect::<Vec<_>>();
+            entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            entries
+        }
+        match self {
+            Self::Node => config
+                .nodes
+                .iter()
+                .map(|node| (node.name.clone(), to_value(node)))
+                .collect(),
+            Self::Task => sorted(&config.tasks),
+            Self::Plugin => config
+                .plugins
+                .iter()
+                .map(|plugin| (plugin.name().to_string(), to_value(plugin)))
+                .collect(),
+            Self::Schedule => sorted(&config.schedules),
+            Self::Repository => sorted(&config.repositories),
+            Self::Setting => {
+                let Value::Object(fields) = to_value(config) else {
+                    unreachable!("the general config is a struct")
+                };
+                fields
+                    .into_iter()
+                    .filter(|(name, _)| !Self::ALL.iter().any(|s| s.field() == Some(name.as_str())))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).expect("config is always serializable")
+}
+
+fn get<'a>(entries: &'a Entries, name: &str) -> Option<&'a Value> {
+    entries.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+}
+
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    let mut fields = old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect::<Vec<_>>();
+    fields.sort_unstable();
+    fields
+}
+
+impl GeneralConfig {
+    /// Entries added, removed or modified to get from this config to the new one
+    pub fn changes(&self, new: &Self) -> Changes {
+        let mut changes = Vec::new();
+        for section in Section::ALL {
+            let old = section.entries(self);
+            let new = section.entries(new);
+            let mut section_changes = Vec::new();
+            for (name, value) in &old {
+                let kind = match get(&new, name) {
+                    None => ChangeKind::Removed,
+                    Some(v) if v != value => ChangeKind::Modified {
+                        fields: changed_fields(value, v),
+                    },
+                    Some(_) => continue,
+                };
+                section_changes.push((name, kind));
+            }
+            for (name, _) in &new {
+                if get(&old, name).is_none() {
+                    section_changes.push((name, ChangeKind::Added));
+                }
+            }
+            section_changes.sort_by_key(|(name, _)| *name);
+            changes.extend(section_changes.into_iter().map(|(name, kind)| Change {
+                section,
+                name: name.clone(),
+                kind,
+            }));
+        }
+        Changes(changes)
+    }
+
+    /// Same as [`GeneralConfig::changes`], for a diff against this config
+    pub fn diff_changes(&self, diff: &GeneralConfigDiff) -> Changes {
+        let mut new = self.clone();
+        new.apply(diff);
+        self.changes(&new)
+    }
+}
+
+/// Three-way merge of two diffs against the same base.
+/// Entries changed by only one side, or in the same way by both, are merged,
+/// anything else is a conflict
+pub fn merge(
+    base: &GeneralConfig,
+    ours: &GeneralConfigDiff,
+    theirs: &GeneralConfigDiff,
+) -> Result<GeneralConfig, MergeConflicts> {
+    let mut ours_config = base.clone();
+    ours_config.apply(ours);
+    let mut theirs_config = base.clone();
+    theirs_config.apply(theirs);
+
+    let mut merged = Map::new();
+    let mut conflicts = Vec::new();
+    for section in Section::ALL {
+        let base = section.entries(base);
+        let ours = section.entries(&ours_config);
+        let theirs = section.entries(&theirs_config);
+        let mut names = Vec::new();
+        for (name, _) in base.iter().chain(&ours).chain(&theirs) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let mut entries = Vec::new();
+        for name in names {
+            let (b, o, t) = (get(&base, name), get(&ours, name), get(&theirs, name));
+            let value = if o == t || t == b {
+                o
+            } else if o == b {
+                t
+            } else {
+                conflicts.push(Conflict {
+                    section,
+                    name: name.clone(),
+                    ours: o.cloned(),
+                    theirs: t.cloned(),
+                });
+                b
+            };
+            if let Some(value) = value {
+                entries.push((name.clone(), value.clone()));
+            }
+        }
+        match section.field() {
+            None => merged.extend(entries),
+            Some(field) if section.is_list() => {
+                merged.insert(
+                    field.to_string(),
+                    Value::Array(entries.into_iter().map(|(_, v)| v).collect()),
+                );
+            }
+            Some(field) => {
+                merged.insert(field.to_string(), Value::Object(entries.into_iter().collect()));
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(MergeConflicts(conflicts));
+    }
+    Ok(serde_json::from_value(Value::Object(merged))
+        .expect("merged entries come from valid configs"))
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Node => "node",
+            Self::Task => "task",
+            Self::Plugin => "plugin",
+            Self::Schedule => "schedule",
+            Self::Repository => "repository",
+            Self::Setting => "setting",
+        })
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ChangeKind::Added => write!(f, "+ {} {}", self.section, self.name),
+            ChangeKind::Removed => write!(f, "- {} {}", self.section, self.name),
+            ChangeKind::Modified { fields } if fields.is_empty() => {
+                write!(f, "~ {} {}", self.section, self.name)
+            }
+            ChangeKind::Modified { fields } => {
+                write!(f, "~ {} {} ({})", self.section, self.name, fields.join(", "))
+            }
+        }
+    }
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Display for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no changes");
+        }
+        for (i, change) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.section, self.name)
+    }
+}
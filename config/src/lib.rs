@@ -1,14 +1,37 @@
 use diff::Diff;
 use url::Url;
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf};
 use url_diff::DiffUrl;
 
 #[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 
 mod url_diff;
+mod validate;
+mod changes;
+mod listen;
+mod lock;
+mod migrate;
+mod params;
+mod policy;
+mod schedule;
+mod secret;
+mod transport;
 pub mod repo;
 
+pub use changes::{merge, Change, ChangeKind, Changes, Conflict, MergeConflicts, Section};
+pub use listen::{ListenAddr, ListenAddrError};
+pub use lock::{
+    LockedArtifact, LockedPlugin, Lockfile, LockfileError, LOCKFILE_VERSION, WASM_ARTIFACT,
+};
+pub use migrate::{migrate, migrate_file, write_file, MigrationError, CONFIG_VERSION};
+pub use params::{Param, ParamError, ParamErrors, ParamSchema, ParamType};
+pub use policy::{Backoff, Concurrency, OnLimit, Resources};
+pub use schedule::{MissedRuns, Schedule, ScheduleError, MAX_CATCH_UP};
+pub use secret::{ClusterKey, Secret, SecretError, SecretRef};
+pub use transport::{transports, Transport, TransportError};
+pub use validate::{Diagnostic, Diagnostics, Severity};
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
 #[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[diff(attr(
@@ -18,7 +41,16 @@ pub struct GeneralConfig {
     pub nodes: Vec<Node>,
     pub tasks: HashMap<String, TaskInfo>,
     #[serde(default)]
-    pub plugins: Vec<Plugin>
+    pub plugins: Vec<Plugin>,
+    #[serde(default)]
+    pub schedules: HashMap<String, Schedule>,
+    /// Plugin repositories by name, each pointing to its manifest
+    #[serde(default)]
+    pub repositories: HashMap<String, Source>,
+    /// SHA-256 fingerprints, in hex, of certificates the node doesn't accept anymore.
+    /// Peers don't pass changes to it on, it is set in the config file of each node
+    #[serde(default)]
+    pub denied_certificates: Vec<String>,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
@@ -31,19 +63,40 @@ pub enum Plugin {
     Name(String),
     NameAndVersion {
         name: String,
+        /// Semver requirement, like `^1.2`, matched against the tags of the plugin's git repository
         version: String
     },
     NameWithRepo {
         name: String,
         repo: String,
+        /// Semver requirement, like `^1.2`, matched against the tags of the plugin's git repository
         #[serde(default)]
         version: Option<String>
     }
 }
 
+impl GeneralConfig {
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+}
+
+impl Plugin {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Name(name)
+            | Self::NameAndVersion { name, .. }
+            | Self::NameWithRepo { name, .. } => name,
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 #[cfg_attr(feature = "schemars", derive(JsonSchema))]
 pub struct Config {
+    /// Format version of the file, see [`CONFIG_VERSION`]. Missing in files from before versioning
+    #[serde(default)]
+    pub version: u32,
     pub general: GeneralConfig,
     pub node: NodeConfig,
 }
@@ -54,13 +107,219 @@ pub struct NodeConfig {
     pub ca_file: PathBuf,
     pub cert_file: PathBuf,
     pub key_file: PathBuf,
-    pub addr: SocketAddr,
+    /// Addresses the chatter between nodes, and the API if `api_listen` is empty, is served on
+    pub listen: Vec<ListenAddr>,
+    /// Addresses the public HTTP API is served on, separately from the chatter
+    #[serde(default)]
+    pub api_listen: Vec<ListenAddr>,
+    /// URL the peers should dial this node at, when it isn't the address in `general.nodes`,
+    /// like behind NAT or port forwarding
+    #[serde(default)]
+    pub advertise: Option<Url>,
     pub name: String,
     #[serde(default)]
     pub priority: u32,
+    /// File with the key used to decrypt the secrets of the config, in base64
+    #[serde(default)]
+    pub cluster_key_file: Option<PathBuf>,
+    /// Where fetched scripts are kept
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+    /// Lockfile pinning the plugins, compared with the peers when connecting
+    #[serde(default = "default_lock_file")]
+    pub lock_file: PathBuf,
+    /// Last occurrence fired of each schedule, so the schedules catch up after a restart
+    #[serde(default = "default_schedules_file")]
+    pub schedules_file: PathBuf,
+    /// Certificate revocation lists, in PEM or DER, reloaded when they change
+    #[serde(default)]
+    pub crl_files: Vec<PathBuf>,
+    /// How many days before expiring a certificate starts being reported
+    #[serde(default = "default_cert_expiry_warning_days")]
+    pub cert_expiry_warning_days: u32,
+    /// Directory of the cluster CA, made by the `ca` command.
+    /// Nodes with one enroll new nodes presenting a join token
+    #[serde(default)]
+    pub ca_dir: Option<PathBuf>,
+    /// Compression of the chatter between nodes, agreed on with each peer
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Formats the chatter between nodes can be serialized in, by preference.
+    /// Each node sends in its most preferred one the peer offers too
+    #[serde(default = "default_codecs")]
+    pub codecs: Vec<WireCodec>,
+    /// Limits on the chatter, so a misbehaving peer can't exhaust the node
+    #[serde(default)]
+    pub limits: ChatterLimits,
     // pub repos: HashMap<String, Source>
 }
 
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from(".gilbert/cache")
+}
+
+fn default_lock_file() -> PathBuf {
+    PathBuf::from("gilbert.lock")
+}
+
+fn default_schedules_file() -> PathBuf {
+    PathBuf::from(".gilbert/schedules.json")
+}
+
+const fn default_cert_expiry_warning_days() -> u32 {
+    14
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionAlgorithm {
+    Zstd,
+    Deflate,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct CompressionConfig {
+    /// Algorithms offered to the peers, by preference. Nothing is compressed if empty
+    #[serde(default)]
+    pub algorithms: Vec<CompressionAlgorithm>,
+    /// Messages smaller than this, in bytes, are sent uncompressed
+    #[serde(default = "default_compression_threshold")]
+    pub threshold: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: Vec::new(),
+            threshold: default_compression_threshold(),
+        }
+    }
+}
+
+const fn default_compression_threshold() -> usize {
+    1024
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum WireCodec {
+    /// Compact, but the peers need the exact same message types
+    Bincode,
+    /// MessagePack, which tolerates fields added to the messages
+    Msgpack,
+    Cbor,
+    /// Readable, for inspecting the traffic
+    Json,
+}
+
+fn default_codecs() -> Vec<WireCodec> {
+    vec![WireCodec::Msgpack, WireCodec::Bincode]
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct ChatterLimits {
+    /// Largest message a peer can send, in bytes, before or after decompression
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    /// Seconds a peer has for the TLS handshake and agreeing on the compression and codecs
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: u64,
+    /// Handshakes in progress from a single IP address, more connections are refused
+    #[serde(default = "default_handshakes_per_ip")]
+    pub handshakes_per_ip: usize,
+}
+
+impl Default for ChatterLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: default_max_message_size(),
+            handshake_timeout: default_handshake_timeout(),
+            handshakes_per_ip: default_handshakes_per_ip(),
+        }
+    }
+}
+
+impl ChatterLimits {
+    /// Smallest allowed message size, messages are sent in frames of up to 16 KiB
+    pub const MIN_MESSAGE_SIZE: usize = 64 * 1024;
+
+    pub const fn handshake_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.handshake_timeout)
+    }
+}
+
+const fn default_max_message_size() -> usize {
+    16 * 1024 * 1024
+}
+
+const fn default_handshake_timeout() -> u64 {
+    10
+}
+
+const fn default_handshakes_per_ip() -> usize {
+    8
+}
+
+/// Fingerprint in the form of [`GeneralConfig::denied_certificates`] and [`Node::fingerprints`],
+/// lowercase hex without separators
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Hex encoded SHA-256, the form of fingerprints and of the hashes in lockfiles
+pub fn sha256_hex(data: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl NodeConfig {
+    /// Names of the fields that changed and can't be applied to a running node.
+    /// The others are read live: `compression`, `codecs` and `limits` apply to the next
+    /// connections, `ca_dir` to the next enrollment and `crl_files` are reloaded right away
+    pub fn restart_required_changes(&self, new: &Self) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.ca_file != new.ca_file {
+            changes.push("ca_file");
+        }
+        if self.cert_file != new.cert_file {
+            changes.push("cert_file");
+        }
+        if self.key_file != new.key_file {
+            changes.push("key_file");
+        }
+        if self.listen != new.listen {
+            changes.push("listen");
+        }
+        if self.api_listen != new.api_listen {
+            changes.push("api_listen");
+        }
+        if self.name != new.name {
+            changes.push("name");
+        }
+        if self.cluster_key_file != new.cluster_key_file {
+            changes.push("cluster_key_file");
+        }
+        if self.cache_dir != new.cache_dir {
+            changes.push("cache_dir");
+        }
+        if self.schedules_file != new.schedules_file {
+            changes.push("schedules_file");
+        }
+        changes
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 #[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[serde(tag = "source")]
@@ -83,6 +342,77 @@ pub enum Source {
     }
 }
 
+impl Diff for Source {
+    type Repr = Option<Self>;
+
+    fn diff(&self, other: &Self) -> Self::Repr {
+        if self == other {
+            None
+        } else {
+            Some(other.clone())
+        }
+    }
+
+    fn apply(&mut self, diff: &Self::Repr) {
+        if let Some(diff) = diff {
+            *self = diff.clone()
+        }
+    }
+
+    fn identity() -> Self {
+        Self::Fs {
+            path: PathBuf::new(),
+        }
+    }
+}
+
+/// Script of a task, either a local path or a source to fetch it from
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(untagged)]
+pub enum Script {
+    Path(PathBuf),
+    Source(Source),
+}
+
+impl Script {
+    /// Path of the script if it is in this machine's filesystem
+    pub fn local_path(&self) -> Option<&std::path::Path> {
+        match self {
+            Self::Path(path) | Self::Source(Source::Fs { path }) => Some(path),
+            Self::Source(_) => None,
+        }
+    }
+}
+
+impl From<PathBuf> for Script {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl Diff for Script {
+    type Repr = Option<Self>;
+
+    fn diff(&self, other: &Self) -> Self::Repr {
+        if self == other {
+            None
+        } else {
+            Some(other.clone())
+        }
+    }
+
+    fn apply(&mut self, diff: &Self::Repr) {
+        if let Some(diff) = diff {
+            *self = diff.clone()
+        }
+    }
+
+    fn identity() -> Self {
+        Self::Path(PathBuf::new())
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
 #[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[diff(attr(
@@ -91,6 +421,46 @@ pub enum Source {
 pub struct Node {
     pub address: DiffUrl,
     pub name: String,
+    #[serde(default)]
+    pub role: Role,
+    /// Free form labels, matched by the `node_selector` of the tasks
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Resources the node offers to jobs, unlimited if missing
+    #[serde(default)]
+    pub resources: Option<Resources>,
+    /// SHA-256 fingerprints of the certificates the node may present.
+    /// Any certificate of the CA with the name of the node is accepted if empty
+    #[serde(default)]
+    pub fingerprints: Vec<String>,
+}
+
+#[derive(
+    Debug, Default, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone, Copy,
+)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Runs jobs
+    #[default]
+    Worker,
+    /// Runs jobs, and is preferred to coordinate the cluster, like firing schedules
+    CoordinatorEligible,
+    /// Takes part in the cluster, but never runs jobs
+    Observer,
+}
+
+impl Role {
+    pub const fn runs_jobs(self) -> bool {
+        !matches!(self, Self::Observer)
+    }
+
+    pub const fn can_coordinate(self) -> bool {
+        matches!(self, Self::CoordinatorEligible)
+    }
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
@@ -104,31 +474,67 @@ pub struct TaskInfo {
     pub allowed_nodes: Option<Vec<String>>,
     #[serde(default)]
     pub disallowed_nodes: Option<Vec<String>>,
-    pub script: PathBuf,
+    /// Labels a node must have, with the same values, to run the task
+    #[serde(default)]
+    pub node_selector: HashMap<String, String>,
+    pub script: Script,
+    /// Seconds a single attempt can run before it's stopped
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Times a failed job is attempted again
+    #[serde(default)]
+    pub max_retries: u32,
+    #[serde(default)]
+    pub backoff: Backoff,
+    /// Resources reserved in the node while the job runs
+    #[serde(default)]
+    pub resources: Resources,
+    #[serde(default)]
+    pub concurrency: Concurrency,
+    /// Secrets given to the job, by the name the script reads them with
+    #[serde(default)]
+    pub secrets: HashMap<String, SecretRef>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
-#[cfg_attr(feature = "schemars", derive(JsonSchema))]
-#[diff(attr(
-    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, Clone)]
-))]
-pub struct Param {
-    name: String,
-    #[serde(rename = "type")]
-    ty: ParamType,
-}
+impl TaskInfo {
+    /// Whether the node is allowed to run the task, according to the allow and deny lists
+    pub fn can_run_on(&self, node: &str) -> bool {
+        self.allowed_nodes
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|n| n == node))
+            && !self
+                .disallowed_nodes
+                .as_ref()
+                .is_some_and(|disallowed| disallowed.iter().any(|n| n == node))
+    }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
-#[cfg_attr(feature = "schemars", derive(JsonSchema))]
-#[diff(attr(
-    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, Clone)]
-))]
-#[serde(rename_all = "lowercase")]
-pub enum ParamType {
-    Number,
-    String,
-    Object,
-    Array,
+    /// Whether the node can run the task, taking into account its role, labels and resources
+    /// besides the allow and deny lists
+    pub fn can_run_on_node(&self, node: &Node) -> bool {
+        node.role.runs_jobs()
+            && self.can_run_on(&node.name)
+            && self
+                .node_selector
+                .iter()
+                .all(|(label, value)| node.labels.get(label) == Some(value))
+            && node
+                .resources
+                .as_ref()
+                .is_none_or(|capacity| self.resources.fits_in(capacity))
+    }
+
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout.map(std::time::Duration::from_secs)
+    }
+
+    /// Checks the params of a job submission, passed by name, against the task's params.
+    /// On success they are returned in the order the script expects them, with defaults filled in
+    pub fn resolve_params(
+        &self,
+        values: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Vec<serde_json::Value>, ParamErrors> {
+        params::resolve_params(&self.params, values)
+    }
 }
 
 // #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -0,0 +1,95 @@
+use std::{fmt::Display, net::SocketAddr, path::PathBuf, str::FromStr};
+
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+
+/// Address a node listens on, written as `host:port` or `unix:<path>` for HTTP,
+/// or `tls:host:port` and `quic:host:port` for the chatter without WebSockets
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Tls(SocketAddr),
+    Quic(SocketAddr),
+}
+
+impl ListenAddr {
+    /// Whether HTTP, and so the API, is served on it
+    pub const fn is_http(&self) -> bool {
+        matches!(self, Self::Tcp(_) | Self::Unix(_))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "invalid listen address {0:?}, expected host:port, unix:<path>, tls:host:port or quic:host:port"
+)]
+pub struct ListenAddrError(String);
+
+impl FromStr for ListenAddr {
+    type Err = ListenAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ListenAddrError(s.to_string());
+        if let Some(path) = s.strip_prefix("unix:") {
+            return if path.is_empty() {
+                Err(error())
+            } else {
+                Ok(Self::Unix(PathBuf::from(path)))
+            };
+        }
+        let (make, addr): (fn(SocketAddr) -> Self, &str) = match s.split_once(':') {
+            Some(("tls", addr)) => (Self::Tls, addr),
+            Some(("quic", addr)) => (Self::Quic, addr),
+            _ => (Self::Tcp, s),
+        };
+        addr.parse().map(make).map_err(|_| error())
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Tls(addr) => write!(f, "tls:{addr}"),
+            Self::Quic(addr) => write!(f, "quic:{addr}"),
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl serde::Serialize for ListenAddr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ListenAddr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl JsonSchema for ListenAddr {
+    fn schema_name() -> String {
+        "ListenAddr".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = String::json_schema(gen).into_object();
+        schema.metadata().description = Some(
+            "host:port, or unix:<path> for a Unix domain socket, serving HTTP. \
+            tls:host:port or quic:host:port serve only the chatter, over TLS or QUIC"
+                .to_string(),
+        );
+        schema.into()
+    }
+}
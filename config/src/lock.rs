@@ -0,0 +1,97 @@
+use std::{collections::BTreeMap, path::Path};
+
+use target_lexicon::Triple;
+use thiserror::Error;
+
+use crate::{sha256_hex, write_file, Source};
+
+pub const LOCKFILE_VERSION: u32 = 1;
+
+/// Key of the WebAssembly build in [`LockedPlugin::artifacts`]
+pub const WASM_ARTIFACT: &str = "wasm";
+
+/// Contents of `gilbert.lock`, the exact plugin builds every node has to run
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct Lockfile {
+    pub version: u32,
+    pub plugins: BTreeMap<String, LockedPlugin>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct LockedPlugin {
+    pub repository: String,
+    /// Commit of the manifest, for plugins coming from git
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Tag the version requirement of the plugin resolved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Where the manifest was read from, pinned to the commit for git
+    pub source: Source,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Builds by target triple, plus [`WASM_ARTIFACT`]
+    pub artifacts: BTreeMap<String, LockedArtifact>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct LockedArtifact {
+    pub source: Source,
+    /// Hex encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+#[derive(Debug, Error)]
+pub enum LockfileError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported lockfile version {0}")]
+    Version(u32),
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lockfile {
+    pub fn new() -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            plugins: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, LockfileError> {
+        let lockfile: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if lockfile.version != LOCKFILE_VERSION {
+            return Err(LockfileError::Version(lockfile.version));
+        }
+        Ok(lockfile)
+    }
+
+    /// Writes the lockfile through a temporary file, so a failure halfway doesn't leave
+    /// a truncated one behind
+    pub fn save(&self, path: &Path) -> Result<(), LockfileError> {
+        write_file(path, &serde_json::to_value(self)?)?;
+        Ok(())
+    }
+
+    /// Hex encoded SHA-256 of the lockfile, equal in nodes locked to the same builds
+    pub fn hash(&self) -> String {
+        let json = serde_json::to_vec(self).expect("lockfile is always serializable");
+        sha256_hex(&json)
+    }
+}
+
+impl LockedPlugin {
+    /// Build to install in the host, native if there is one
+    pub fn artifact_for(&self, host: &Triple) -> Option<&LockedArtifact> {
+        self.artifacts
+            .get(&host.to_string())
+            .or_else(|| self.artifacts.get(WASM_ARTIFACT))
+    }
+}
@@ -0,0 +1,112 @@
+use std::{io::Write, path::Path};
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::Config;
+
+/// Version of the config format the structs of this crate deserialize
+pub const CONFIG_VERSION: u32 = 2;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), MigrationError>;
+
+/// Migrations from version `i` to `i + 1`, files without a version are version 0
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("the config is not a JSON object")]
+    NotAnObject,
+    #[error("invalid config version {0}")]
+    InvalidVersion(Value),
+    #[error("config version {0} is newer than the supported version {CONFIG_VERSION}")]
+    TooNew(u32),
+    #[error("unable to migrate from version {from}: {message}")]
+    Failed { from: u32, message: String },
+}
+
+/// Upgrades a config document to [`CONFIG_VERSION`], returning the version it had.
+/// Fields the migrations don't know about, like `$schema`, are kept as they were
+pub fn migrate(config: &mut Value) -> Result<u32, MigrationError> {
+    let object = config.as_object_mut().ok_or(MigrationError::NotAnObject)?;
+    let version = match object.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| MigrationError::InvalidVersion(v.clone()))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(MigrationError::TooNew(version));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(object)?;
+    }
+    object.insert("version".into(), CONFIG_VERSION.into());
+    Ok(version)
+}
+
+impl Config {
+    /// Parses a config of any supported version
+    pub fn from_json(json: &str) -> Result<Self, MigrationError> {
+        let mut value = serde_json::from_str(json)?;
+        migrate(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MigrationError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Rewrites the config file in the latest version, returning the version it had.
+/// The file is left untouched if it already was in the latest version
+pub fn migrate_file(path: &Path) -> Result<u32, MigrationError> {
+    let mut value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let version = migrate(&mut value)?;
+    // Make sure the result is usable before replacing the old file
+    serde_json::from_value::<Config>(value.clone())?;
+    if version != CONFIG_VERSION {
+        write_file(path, &value)?;
+    }
+    Ok(version)
+}
+
+/// Files from before versioning, every field added since has a default
+fn v0_to_v1(_config: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    Ok(())
+}
+
+/// `node.addr` became the list `node.listen`
+fn v1_to_v2(config: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    let Some(node) = config.get_mut("node").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    if let Some(addr) = node.remove("addr") {
+        if node.contains_key("listen") {
+            return Err(MigrationError::Failed {
+                from: 1,
+                message: "node has both addr and listen".to_string(),
+            });
+        }
+        node.insert("listen".into(), Value::Array(vec![addr]));
+    }
+    Ok(())
+}
+
+/// Writes a config document to a temporary file first, and renames it over the old one,
+/// so a failure halfway doesn't leave a truncated config behind
+pub fn write_file(path: &Path, config: &Value) -> std::io::Result<()> {
+    let mut contents = serde_json::to_string_pretty(config)?;
+    contents.push('\n');
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,435 @@
+use std::fmt::Display;
+
+use diff::Diff;
+use serde_json::{Map, Number, Value};
+
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+
+use crate::validate::{Diagnostics, JsonPath};
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+pub struct Param {
+    pub name: String,
+    #[serde(flatten)]
+    pub schema: ParamSchema,
+    /// An optional param without a default is passed as `null` when it's left out
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl Param {
+    pub const fn is_required(&self) -> bool {
+        !self.optional && self.schema.default.is_none()
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct ParamSchema {
+    #[serde(flatten)]
+    pub ty: ParamType,
+    #[serde(default)]
+    pub nullable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ParamType {
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        minimum: Option<Number>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        maximum: Option<Number>,
+    },
+    Integer {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        minimum: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        maximum: Option<i64>,
+    },
+    Boolean,
+    String {
+        /// Regex the whole string has to match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_length: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_length: Option<usize>,
+    },
+    Enum {
+        values: Vec<Value>,
+    },
+    Object {
+        /// If not present any object is accepted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        properties: Option<Vec<Param>>,
+    },
+    Array {
+        /// If not present the items aren't checked
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        items: Option<Box<ParamSchema>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_items: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_items: Option<usize>,
+    },
+}
+
+impl Diff for ParamSchema {
+    type Repr = Option<Self>;
+
+    fn diff(&self, other: &Self) -> Self::Repr {
+        if self == other {
+            None
+        } else {
+            Some(other.clone())
+        }
+    }
+
+    fn apply(&mut self, diff: &Self::Repr) {
+        if let Some(diff) = diff {
+            *self = diff.clone()
+        }
+    }
+
+    fn identity() -> Self {
+        Self {
+            ty: ParamType::Object { properties: None },
+            nullable: false,
+            default: None,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct ParamError {
+    /// JSON path to the offending value, like `$.x`
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, PartialEq, Eq, Clone)]
+#[serde(transparent)]
+pub struct ParamErrors(Vec<ParamError>);
+
+impl ParamErrors {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ParamError> + '_ {
+        self.0.iter()
+    }
+
+    fn push<M: Into<String>>(&mut self, path: &JsonPath, message: M) {
+        self.0.push(ParamError {
+            path: path.to_string(),
+            message: message.into(),
+        })
+    }
+}
+
+impl Display for ParamErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            error.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParamErrors {}
+
+/// Checks the named values against the params, filling in the defaults.
+/// Left out optional params are `None`
+fn resolve_named(
+    params: &[Param],
+    mut values: Map<String, Value>,
+    path: &JsonPath,
+    errors: &mut ParamErrors,
+) -> Vec<Option<Value>> {
+    let resolved = params
+        .iter()
+        .map(|param| {
+            let param_path = path.key(&param.name);
+            match values.remove(&param.name) {
+                Some(value) => Some(param.schema.resolve(value, &param_path, errors)),
+                None => {
+                    if param.is_required() {
+                        errors.push(&param_path, "missing required parameter");
+                    }
+                    param.schema.default.clone()
+                }
+            }
+        })
+        .collect();
+    for key in values.keys() {
+        errors.push(&path.key(key), "unknown parameter");
+    }
+    resolved
+}
+
+/// Checks the params passed by name, and returns them in the order the task expects them
+pub fn resolve_params(
+    params: &[Param],
+    values: Map<String, Value>,
+) -> Result<Vec<Value>, ParamErrors> {
+    let mut errors = ParamErrors::default();
+    let resolved = resolve_named(params, values, &JsonPath::root(), &mut errors);
+    if errors.is_empty() {
+        Ok(resolved
+            .into_iter()
+            .map(|v| v.unwrap_or(Value::Null))
+            .collect())
+    } else {
+        Err(errors)
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+impl ParamSchema {
+    /// Checks the value against the schema, returning it with any nested defaults filled in
+    fn resolve(&self, value: Value, path: &JsonPath, errors: &mut ParamErrors) -> Value {
+        if value.is_null() {
+            if !self.nullable {
+                errors.push(path, "value can't be null");
+            }
+            return value;
+        }
+        match (&self.ty, value) {
+            (ParamType::Number { minimum, maximum }, Value::Number(n)) => {
+                let x = n.as_f64().unwrap_or(f64::NAN);
+                if let Some(min) = minimum.as_ref().and_then(Number::as_f64) {
+                    if x < min {
+                        errors.push(path, format!("{n} is less than the minimum {min}"));
+                    }
+                }
+                if let Some(max) = maximum.as_ref().and_then(Number::as_f64) {
+                    if x > max {
+                        errors.push(path, format!("{n} is greater than the maximum {max}"));
+                    }
+                }
+                Value::Number(n)
+            }
+            (ParamType::Integer { minimum, maximum }, Value::Number(n)) => {
+                if let Some(x) = n.as_i64() {
+                    if let Some(min) = minimum.filter(|&min| x < min) {
+                        errors.push(path, format!("{x} is less than the minimum {min}"));
+                    }
+                    if let Some(max) = maximum.filter(|&max| x > max) {
+                        errors.push(path, format!("{x} is greater than the maximum {max}"));
+                    }
+                } else if n.is_u64() {
+                    // Too big for an i64, so it's above any maximum
+                    if let Some(max) = maximum {
+                        errors.push(path, format!("{n} is greater than the maximum {max}"));
+                    }
+                } else {
+                    errors.push(path, format!("expected integer, found {n}"));
+                }
+                Value::Number(n)
+            }
+            (ParamType::Boolean, value @ Value::Bool(_)) => value,
+            (
+                ParamType::String {
+                    pattern,
+                    min_length,
+                    max_length,
+                },
+                Value::String(s),
+            ) => {
+                let len = s.chars().count();
+                if let Some(min) = min_length.filter(|&min| len < min) {
+                    errors.push(path, format!("string is shorter than {min}"));
+                }
+                if let Some(max) = max_length.filter(|&max| len > max) {
+                    errors.push(path, format!("string is longer than {max}"));
+                }
+                if let Some(pattern) = pattern {
+                    match regex::Regex::new(&format!("^(?:{pattern})$")) {
+                        Ok(regex) if !regex.is_match(&s) => {
+                            errors.push(path, format!("string doesn't match {pattern:?}"));
+                        }
+                        Ok(_) => (),
+                        Err(e) => errors.push(path, format!("invalid pattern {pattern:?}: {e}")),
+                    }
+                }
+                Value::String(s)
+            }
+            (ParamType::Enum { values }, value) => {
+                if !values.contains(&value) {
+                    errors.push(path, format!("{value} is not one of the allowed values"));
+                }
+                value
+            }
+            (ParamType::Object { properties: None }, value @ Value::Object(_)) => value,
+            (
+                ParamType::Object {
+                    properties: Some(properties),
+                },
+                Value::Object(obj),
+            ) => Value::Object(
+                properties
+                    .iter()
+                    .zip(resolve_named(properties, obj, path, errors))
+                    .filter_map(|(param, value)| value.map(|value| (param.name.clone(), value)))
+                    .collect(),
+            ),
+            (
+                ParamType::Array {
+                    items,
+                    min_items,
+                    max_items,
+                },
+                Value::Array(arr),
+            ) => {
+                if let Some(min) = min_items.filter(|&min| arr.len() < min) {
+                    errors.push(path, format!("array has less than {min} items"));
+                }
+                if let Some(max) = max_items.filter(|&max| arr.len() > max) {
+                    errors.push(path, format!("array has more than {max} items"));
+                }
+                match items {
+                    Some(items) => Value::Array(
+                        arr.into_iter()
+                            .enumerate()
+                            .map(|(i, item)| items.resolve(item, &path.index(i), errors))
+                            .collect(),
+                    ),
+                    None => Value::Array(arr),
+                }
+            }
+            (ty, value) => {
+                errors.push(
+                    path,
+                    format!("expected {}, found {}", ty.name(), type_name(&value)),
+                );
+                value
+            }
+        }
+    }
+
+    /// Checks the schema itself, like the pattern being a valid regex
+    /// and the default matching the schema
+    pub(crate) fn check_schema(&self, path: &JsonPath, diagnostics: &mut Diagnostics) {
+        match &self.ty {
+            ParamType::Number { minimum, maximum } => {
+                if let (Some(min), Some(max)) = (
+                    minimum.as_ref().and_then(Number::as_f64),
+                    maximum.as_ref().and_then(Number::as_f64),
+                ) {
+                    if min > max {
+                        diagnostics.error(path.field("minimum"), "minimum is greater than maximum");
+                    }
+                }
+            }
+            ParamType::Integer {
+                minimum: Some(min),
+                maximum: Some(max),
+            } if min > max => {
+                diagnostics.error(path.field("minimum"), "minimum is greater than maximum");
+            }
+            ParamType::String {
+                pattern,
+                min_length,
+                max_length,
+            } => {
+                if let Some(pattern) = pattern {
+                    if let Err(e) = regex::Regex::new(pattern) {
+                        diagnostics.error(path.field("pattern"), format!("invalid regex: {e}"));
+                    }
+                }
+                if let (Some(min), Some(max)) = (min_length, max_length) {
+                    if min > max {
+                        diagnostics.error(
+                            path.field("min_length"),
+                            "min_length is greater than max_length",
+                        );
+                    }
+                }
+            }
+            ParamType::Enum { values } if values.is_empty() => {
+                diagnostics.error(path.field("values"), "no values are allowed");
+            }
+            ParamType::Object {
+                properties: Some(properties),
+            } => {
+                for (i, param) in properties.iter().enumerate() {
+                    param
+                        .schema
+                        .check_schema(&path.field("properties").index(i), diagnostics);
+                }
+            }
+            ParamType::Array {
+                items,
+                min_items,
+                max_items,
+            } => {
+                if let Some(items) = items {
+                    items.check_schema(&path.field("items"), diagnostics);
+                }
+                if let (Some(min), Some(max)) = (min_items, max_items) {
+                    if min > max {
+                        diagnostics.error(
+                            path.field("min_items"),
+                            "min_items is greater than max_items",
+                        );
+                    }
+                }
+            }
+            _ => (),
+        }
+        if let Some(default) = &self.default {
+            let mut errors = ParamErrors::default();
+            self.resolve(default.clone(), &JsonPath::root(), &mut errors);
+            for error in errors.0 {
+                diagnostics.error(
+                    path.field("default"),
+                    format!("default doesn't match the schema: {error}"),
+                );
+            }
+        }
+    }
+}
+
+impl ParamType {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Number { .. } => "number",
+            Self::Integer { .. } => "integer",
+            Self::Boolean => "boolean",
+            Self::String { .. } => "string",
+            Self::Enum { .. } => "enum",
+            Self::Object { .. } => "object",
+            Self::Array { .. } => "array",
+        }
+    }
+}
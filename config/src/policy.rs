@@ -0,0 +1,129 @@
+use std::{collections::HashMap, time::Duration};
+
+use diff::Diff;
+
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+
+/// How long to wait before retrying a failed job
+#[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backoff {
+    Fixed {
+        /// Seconds between attempts
+        delay: u64,
+    },
+    Exponential {
+        /// Seconds before the first retry, doubled on every following one
+        initial: u64,
+        /// Upper bound for the delay, in seconds
+        #[serde(default)]
+        max: Option<u64>,
+    },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::Fixed { delay: 0 }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given retry, the first retry being 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        match self {
+            Self::Fixed { delay } => Duration::from_secs(*delay),
+            Self::Exponential { initial, max } => {
+                let delay = initial.saturating_mul(2u64.saturating_pow(retry));
+                Duration::from_secs(max.map_or(delay, |max| delay.min(max)))
+            }
+        }
+    }
+}
+
+/// Amount of resources, requested by a task
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+pub struct Resources {
+    /// CPU cores
+    #[serde(default)]
+    pub cpu: u32,
+    /// Memory in MiB
+    #[serde(default)]
+    pub memory: u64,
+    /// User defined counters, like licenses or GPUs
+    #[serde(default)]
+    pub custom: HashMap<String, u64>,
+}
+
+impl Resources {
+    pub fn is_empty(&self) -> bool {
+        self.cpu == 0 && self.memory == 0 && self.custom.values().all(|&x| x == 0)
+    }
+
+    /// Whether these resources fit in the capacity.
+    /// Custom counters missing from the capacity are taken as 0
+    pub fn fits_in(&self, capacity: &Self) -> bool {
+        self.cpu <= capacity.cpu
+            && self.memory <= capacity.memory
+            && self
+                .custom
+                .iter()
+                .all(|(name, &x)| x <= capacity.custom.get(name).copied().unwrap_or(0))
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.cpu += other.cpu;
+        self.memory += other.memory;
+        for (name, x) in &other.custom {
+            *self.custom.entry(name.clone()).or_default() += x;
+        }
+    }
+
+    pub fn sub(&mut self, other: &Self) {
+        self.cpu = self.cpu.saturating_sub(other.cpu);
+        self.memory = self.memory.saturating_sub(other.memory);
+        for (name, x) in &other.custom {
+            if let Some(y) = self.custom.get_mut(name) {
+                *y = y.saturating_sub(*x);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+pub struct Concurrency {
+    /// Max jobs of the task running at the same time in a single node
+    #[serde(default)]
+    pub per_node: Option<u32>,
+    /// What happens to a job that would go over the limit
+    #[serde(default)]
+    pub on_limit: OnLimit,
+}
+
+#[derive(
+    Debug, Default, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone, Copy,
+)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+#[serde(rename_all = "lowercase")]
+pub enum OnLimit {
+    /// Wait until another job finishes
+    #[default]
+    Queue,
+    /// Fail the job straight away
+    Reject,
+}
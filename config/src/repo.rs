@@ -18,10 +18,12 @@ pub struct Repository {
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Plugin {
 	#[serde(default)]
-	args: Vec<String>,
-	binaries: HashMap<Triple, String>,
+	pub args: Vec<String>,
+	/// Location of the binary for every supported target, relative to the manifest or a URL
+	pub binaries: HashMap<Triple, String>,
+	/// Location of the WebAssembly build, used on targets without a binary
 	#[serde(default)]
-	wasm_base: Option<String>
+	pub wasm_base: Option<String>
 }
 
 // #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use diff::Diff;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+
+/// Upper bound of the missed runs fired when catching up, so a long outage doesn't flood the cluster
+pub const MAX_CATCH_UP: usize = 100;
+
+/// Runs a task with fixed params every time the cron expression matches
+#[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct Schedule {
+    pub task: String,
+    /// Cron expression, like `0 3 * * *`. Seconds can be given as a sixth field at the start
+    pub cron: String,
+    /// IANA time zone the expression is evaluated in, like `Europe/Madrid`. UTC if missing
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Params passed to the task, by name
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    #[serde(default)]
+    pub missed: MissedRuns,
+}
+
+/// What to do with the occurrences that went by while no node could fire them
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRuns {
+    /// Forget them and wait for the next occurrence
+    #[default]
+    Skip,
+    /// Run the task once for all of them
+    RunOnce,
+    /// Run the task for every one of them, up to [`MAX_CATCH_UP`]
+    CatchUp,
+}
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("invalid cron expression: {0}")]
+    Cron(#[from] croner::errors::CronError),
+    #[error("unknown time zone {0:?}")]
+    TimeZone(String),
+}
+
+impl Schedule {
+    pub fn parse_cron(&self) -> Result<Cron, ScheduleError> {
+        Ok(Cron::new(&self.cron).with_seconds_optional().parse()?)
+    }
+
+    pub fn parse_timezone(&self) -> Result<Tz, ScheduleError> {
+        match &self.timezone {
+            None => Ok(Tz::UTC),
+            Some(name) => name
+                .parse()
+                .map_err(|_| ScheduleError::TimeZone(name.clone())),
+        }
+    }
+
+    /// First occurrence strictly after the given time
+    pub fn next_after(&self, after: DateTime<Utc>) -> Result<DateTime<Utc>, ScheduleError> {
+        let tz = self.parse_timezone()?;
+        let next = self
+            .parse_cron()?
+            .find_next_occurrence(&after.with_timezone(&tz), false)?;
+        Ok(next.with_timezone(&Utc))
+    }
+
+    /// Occurrences in `(after, until]`
+    pub fn occurrences(
+        &self,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<impl Iterator<Item = DateTime<Utc>>, ScheduleError> {
+        let tz = self.parse_timezone()?;
+        Ok(self
+            .parse_cron()?
+            .iter_after(after.with_timezone(&tz))
+            .map(|x| x.with_timezone(&Utc))
+            .take_while(move |x| *x <= until))
+    }
+
+    /// Occurrences in `(last, now]` that have to be fired according to the missed runs policy
+    pub fn missed_runs(
+        &self,
+        last: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, ScheduleError> {
+        Ok(match self.missed {
+            MissedRuns::Skip => Vec::new(),
+            MissedRuns::RunOnce => self.occurrences(last, now)?.last().into_iter().collect(),
+            MissedRuns::CatchUp => {
+                let mut latest = VecDeque::with_capacity(MAX_CATCH_UP);
+                for x in self.occurrences(last, now)? {
+                    if latest.len() == MAX_CATCH_UP {
+                        latest.pop_front();
+                    }
+                    latest.push_back(x);
+                }
+                latest.into()
+            }
+        })
+    }
+}
+
+impl Diff for Schedule {
+    type Repr = Option<Self>;
+
+    fn diff(&self, other: &Self) -> Self::Repr {
+        if self == other {
+            None
+        } else {
+            Some(other.clone())
+        }
+    }
+
+    fn apply(&mut self, diff: &Self::Repr) {
+        if let Some(diff) = diff {
+            *self = diff.clone()
+        }
+    }
+
+    fn identity() -> Self {
+        Self {
+            task: String::new(),
+            cron: String::new(),
+            timezone: None,
+            params: Map::new(),
+            missed: MissedRuns::default(),
+        }
+    }
+}
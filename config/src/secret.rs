@@ -0,0 +1,157 @@
+use std::{fmt::Debug, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use diff::Diff;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use thiserror::Error;
+
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+
+/// Where the value of a secret comes from.
+/// Only the reference is part of the config, the value is looked up by the node running the job
+#[derive(Debug, serde::Deserialize, serde::Serialize, Diff, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[diff(attr(
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize, Clone)]
+))]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum SecretRef {
+    /// Environment variable of the node
+    Env { var: String },
+    /// File in the node, its contents with the trailing newline removed
+    File { path: PathBuf },
+    /// Value encrypted with the cluster key, in base64
+    Encrypted { value: String },
+}
+
+/// Resolved value of a secret. It can't be serialized or printed, so it doesn't end up in logs or messages
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("environment variable {0} is not set")]
+    MissingEnv(String),
+    #[error("unable to read {}: {1}", .0.display())]
+    File(PathBuf, std::io::Error),
+    #[error("unable to write {}: {1}", .0.display())]
+    Write(PathBuf, std::io::Error),
+    #[error("no cluster key to decrypt the secret")]
+    NoClusterKey,
+    #[error("invalid cluster key")]
+    InvalidKey,
+    #[error("unable to decrypt the secret")]
+    Decrypt,
+}
+
+/// Symmetric key shared by the nodes of the cluster, to encrypt the secrets placed in the config
+pub struct ClusterKey(LessSafeKey);
+
+impl ClusterKey {
+    /// Parses a key encoded in base64
+    pub fn from_base64(encoded: &str) -> Result<Self, SecretError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| SecretError::InvalidKey)?;
+        let key =
+            UnboundKey::new(&CHACHA20_POLY1305, &bytes).map_err(|_| SecretError::InvalidKey)?;
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    /// Reads a key file, holding the key in base64
+    pub fn load(path: &std::path::Path) -> Result<Self, SecretError> {
+        let encoded =
+            std::fs::read_to_string(path).map_err(|e| SecretError::File(path.to_path_buf(), e))?;
+        Self::from_base64(&encoded)
+    }
+
+    /// Generates a random key, returned encoded in base64
+    pub fn generate() -> String {
+        let mut bytes = [0; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("unable to generate a random key");
+        STANDARD.encode(bytes)
+    }
+
+    /// Generates a random key and writes it to a new key file, only readable by its owner.
+    /// An existing file is never replaced, as the secrets encrypted with it would be lost
+    pub fn create(path: &std::path::Path) -> Result<Self, SecretError> {
+        let encoded = Self::generate();
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let write_error = |e| SecretError::Write(path.to_path_buf(), e);
+        let mut file = options.open(path).map_err(write_error)?;
+        std::io::Write::write_all(&mut file, format!("{encoded}\n").as_bytes())
+            .map_err(write_error)?;
+        Self::from_base64(&encoded)
+    }
+
+    /// Encrypts a value to be placed in a [`SecretRef::Encrypted`]
+    pub fn encrypt(&self, value: &str) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("unable to generate a random nonce");
+        let mut in_out = value.as_bytes().to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .expect("unable to encrypt the secret");
+        let mut out = nonce.to_vec();
+        out.extend(in_out);
+        STANDARD.encode(out)
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<Secret, SecretError> {
+        let bytes = STANDARD.decode(value).map_err(|_| SecretError::Decrypt)?;
+        if bytes.len() < NONCE_LEN {
+            return Err(SecretError::Decrypt);
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| SecretError::Decrypt)?;
+        let mut in_out = ciphertext.to_vec();
+        let plain = self
+            .0
+            .open_in_place(nonce, Aad::empty(), &mut in_out)
+            .map_err(|_| SecretError::Decrypt)?;
+        String::from_utf8(plain.to_vec())
+            .map(Secret)
+            .map_err(|_| SecretError::Decrypt)
+    }
+}
+
+impl SecretRef {
+    pub fn resolve(&self, key: Option<&ClusterKey>) -> Result<Secret, SecretError> {
+        match self {
+            Self::Env { var } => std::env::var(var)
+                .map(Secret)
+                .map_err(|_| SecretError::MissingEnv(var.clone())),
+            Self::File { path } => std::fs::read_to_string(path)
+                .map(|value| Secret(value.trim_end_matches(['\r', '\n']).to_string()))
+                .map_err(|e| SecretError::File(path.clone(), e)),
+            Self::Encrypted { value } => key.ok_or(SecretError::NoClusterKey)?.decrypt(value),
+        }
+    }
+}
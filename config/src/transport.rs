@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+use url::{Position, Url};
+
+/// A way of dialing a node. The scheme of a node address lists the ones it accepts,
+/// joined with `+` like `quic+https://node1:8080`.
+/// Sorted by preference: QUIC is tried first, whatever order the address lists them in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Transport {
+    /// QUIC on the UDP port of the address
+    Quic,
+    /// TLS straight over TCP
+    Tls,
+    /// TLS inside a WebSocket, over HTTPS
+    Https,
+    /// TLS inside a WebSocket, over plain HTTP
+    Http,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TransportError {
+    #[error("unknown transport {0:?}, expected quic, tls, https or http")]
+    Unknown(String),
+    #[error("{0} and {1} can't both use the TCP port of the address")]
+    SharedPort(Transport, Transport),
+    #[error("the address needs a port")]
+    NoPort,
+}
+
+impl Transport {
+    pub const fn scheme(self) -> &'static str {
+        match self {
+            Self::Quic => "quic",
+            Self::Tls => "tls",
+            Self::Https => "https",
+            Self::Http => "http",
+        }
+    }
+
+    /// The address with only this transport in its scheme
+    pub fn address(self, address: &Url) -> Url {
+        Url::parse(&format!(
+            "{}{}",
+            self.scheme(),
+            &address[Position::AfterScheme..]
+        ))
+        .expect("the address only changes scheme")
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.scheme())
+    }
+}
+
+/// Transports a node address accepts, sorted by preference rather than in the address's order
+pub fn transports(address: &Url) -> Result<Vec<Transport>, TransportError> {
+    let mut transports = address
+        .scheme()
+        .split('+')
+        .map(|scheme| match scheme {
+            "quic" => Ok(Transport::Quic),
+            "tls" => Ok(Transport::Tls),
+            "https" => Ok(Transport::Https),
+            "http" => Ok(Transport::Http),
+            _ => Err(TransportError::Unknown(scheme.to_string())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    transports.sort_unstable();
+    transports.dedup();
+    let mut tcp = transports.iter().filter(|t| **t != Transport::Quic);
+    if let (Some(&first), Some(&second)) = (tcp.next(), tcp.next()) {
+        return Err(TransportError::SharedPort(first, second));
+    }
+    // Only plain http and https have a default port
+    if address.port_or_known_default().is_none() {
+        return Err(TransportError::NoPort);
+    }
+    Ok(transports)
+}
@@ -17,6 +17,12 @@ impl std::fmt::Display for DiffUrl {
     }
 }
 
+impl From<Url> for DiffUrl {
+    fn from(url: Url) -> Self {
+        Self(url)
+    }
+}
+
 impl Deref for DiffUrl {
     type Target = Url;
 
@@ -0,0 +1,534 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::Component,
+};
+
+use rustls::ServerName;
+use url::Url;
+
+use crate::{
+    normalize_fingerprint, transports, ChatterLimits, Config, GeneralConfig, ListenAddr, Node,
+    NodeConfig, Plugin, Schedule, Script, SecretRef, Source, TaskInfo,
+};
+
+#[derive(Debug, serde::Serialize, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => f.write_str("warning"),
+            Self::Error => f.write_str("error"),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    /// JSON path to the offending value, like `$.general.tasks.job1.script`
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}: {}", self.severity, self.path, self.message)
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, PartialEq, Eq, Clone)]
+#[serde(transparent)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> + '_ {
+        self.0.iter()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> + '_ {
+        self.0.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> + '_ {
+        self.0.iter().filter(|d| d.severity == Severity::Warning)
+    }
+
+    fn push<P: Display, M: Into<String>>(&mut self, severity: Severity, path: P, message: M) {
+        self.0.push(Diagnostic {
+            path: path.to_string(),
+            severity,
+            message: message.into(),
+        })
+    }
+
+    pub(crate) fn error<P: Display, M: Into<String>>(&mut self, path: P, message: M) {
+        self.push(Severity::Error, path, message)
+    }
+
+    pub(crate) fn warning<P: Display, M: Into<String>>(&mut self, path: P, message: M) {
+        self.push(Severity::Warning, path, message)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Small builder for the JSON paths reported in diagnostics
+#[derive(Debug, Clone)]
+pub(crate) struct JsonPath(String);
+
+impl JsonPath {
+    pub(crate) fn root() -> Self {
+        Self("$".into())
+    }
+
+    pub(crate) fn field(&self, name: &str) -> Self {
+        Self(format!("{}.{name}", self.0))
+    }
+
+    pub(crate) fn key(&self, key: &str) -> Self {
+        if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.field(key)
+        } else {
+            Self(format!("{}[{key:?}]", self.0))
+        }
+    }
+
+    pub(crate) fn index(&self, idx: usize) -> Self {
+        Self(format!("{}[{idx}]", self.0))
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Config {
+    /// Checks the config for semantic problems that deserialization can't catch.
+    /// All problems are collected, so a single run reports everything that is wrong.
+    pub fn validate(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+        let root = JsonPath::root();
+        validate_general(&self.general, &root.field("general"), &mut diagnostics);
+        validate_node(
+            &self.node,
+            &self.general,
+            &root.field("node"),
+            &mut diagnostics,
+        );
+        let this_node = self.general.node(&self.node.name);
+        for (name, task) in sorted_tasks(&self.general) {
+            let path = root.field("general").field("tasks").key(name);
+            let runs_here =
+                this_node.map_or(task.can_run_on(&self.node.name), |n| task.can_run_on_node(n));
+            if let Some(script) = task.script.local_path() {
+                if runs_here && !script.exists() {
+                    diagnostics.error(
+                        path.field("script"),
+                        format!(
+                            "script {} does not exist, and this node is allowed to run the task",
+                            script.display()
+                        ),
+                    );
+                }
+            }
+            if runs_here && self.node.cluster_key_file.is_none() {
+                let mut secrets = task.secrets.iter().collect::<Vec<_>>();
+                secrets.sort_unstable_by_key(|(name, _)| *name);
+                for (name, secret) in secrets {
+                    if matches!(secret, SecretRef::Encrypted { .. }) {
+                        diagnostics.error(
+                            path.field("secrets").key(name),
+                            "encrypted secret, but this node has no cluster_key_file to decrypt it",
+                        );
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+impl GeneralConfig {
+    /// Checks the node independent parts of the config.
+    /// This is what can be validated from a config received from a peer.
+    pub fn validate(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+        validate_general(self, &JsonPath::root(), &mut diagnostics);
+        diagnostics
+    }
+}
+
+/// Tasks sorted by name, so diagnostics come out in a stable order
+fn sorted_tasks(config: &GeneralConfig) -> Vec<(&String, &TaskInfo)> {
+    let mut tasks = config.tasks.iter().collect::<Vec<_>>();
+    tasks.sort_unstable_by_key(|(name, _)| *name);
+    tasks
+}
+
+fn validate_general(config: &GeneralConfig, path: &JsonPath, diagnostics: &mut Diagnostics) {
+    let mut names = HashMap::new();
+    let mut addresses = HashMap::new();
+    for (i, node) in config.nodes.iter().enumerate() {
+        let node_path = path.field("nodes").index(i);
+        if node.name.is_empty() {
+            diagnostics.error(node_path.field("name"), "node name is empty");
+        } else if ServerName::try_from(node.name.as_str()).is_err() {
+            // Peers check the name against the DNS names of the node's certificate
+            diagnostics.error(
+                node_path.field("name"),
+                format!("node name {:?} is not a valid DNS name", node.name),
+            );
+        }
+        if let Some(first) = names.insert(node.name.as_str(), i) {
+            diagnostics.error(
+                node_path.field("name"),
+                format!(
+                    "duplicate node name {:?}, already used by {}",
+                    node.name,
+                    path.field("nodes").index(first)
+                ),
+            );
+        }
+        validate_fingerprints(&node.fingerprints, &node_path.field("fingerprints"), diagnostics);
+        if let Err(e) = transports(&node.address) {
+            diagnostics.error(node_path.field("address"), e.to_string());
+        }
+        if let Some(first) = addresses.insert(node.address.as_str(), i) {
+            diagnostics.warning(
+                node_path.field("address"),
+                format!(
+                    "address {} is also used by {}",
+                    node.address,
+                    path.field("nodes").index(first)
+                ),
+            );
+        }
+    }
+
+    for (name, task) in sorted_tasks(config) {
+        validate_task(
+            task,
+            &config.nodes,
+            &names,
+            &path.field("tasks").key(name),
+            diagnostics,
+        );
+    }
+
+    let mut repositories = config.repositories.iter().collect::<Vec<_>>();
+    repositories.sort_unstable_by_key(|(name, _)| *name);
+    for (name, source) in repositories {
+        validate_source(source, &path.field("repositories").key(name), diagnostics);
+    }
+
+    validate_fingerprints(
+        &config.denied_certificates,
+        &path.field("denied_certificates"),
+        diagnostics,
+    );
+
+    let mut plugins = HashSet::new();
+    for (i, plugin) in config.plugins.iter().enumerate() {
+        if !plugins.insert(plugin.name()) {
+            diagnostics.warning(
+                path.field("plugins").index(i),
+                format!("plugin {:?} is listed more than once", plugin.name()),
+            );
+        }
+        if let Plugin::NameWithRepo { repo, .. } = plugin {
+            if !config.repositories.contains_key(repo) {
+                diagnostics.error(
+                    path.field("plugins").index(i).field("repo"),
+                    format!("unknown repository {repo:?}"),
+                );
+            }
+        }
+    }
+
+    let mut schedules = config.schedules.iter().collect::<Vec<_>>();
+    schedules.sort_unstable_by_key(|(name, _)| *name);
+    for (name, schedule) in schedules {
+        validate_schedule(config, schedule, &path.field("schedules").key(name), diagnostics);
+    }
+}
+
+fn validate_schedule(
+    config: &GeneralConfig,
+    schedule: &Schedule,
+    path: &JsonPath,
+    diagnostics: &mut Diagnostics,
+) {
+    if let Err(e) = schedule.parse_cron() {
+        diagnostics.error(path.field("cron"), e.to_string());
+    }
+    if let Err(e) = schedule.parse_timezone() {
+        diagnostics.error(path.field("timezone"), e.to_string());
+    }
+    let Some(task) = config.tasks.get(&schedule.task) else {
+        diagnostics.error(
+            path.field("task"),
+            format!("unknown task {:?}", schedule.task),
+        );
+        return;
+    };
+    if let Err(errors) = task.resolve_params(schedule.params.clone()) {
+        for e in errors.iter() {
+            diagnostics.error(
+                format!("{}{}", path.field("params"), e.path.trim_start_matches('$')),
+                &e.message,
+            );
+        }
+    }
+}
+
+/// Git sources come from the config every node shares, so they're limited to remote repositories
+fn validate_source(source: &Source, path: &JsonPath, diagnostics: &mut Diagnostics) {
+    let Source::Git { repo, version, .. } = source else {
+        return;
+    };
+    if !Url::parse(repo).is_ok_and(|url| matches!(url.scheme(), "https" | "ssh")) {
+        diagnostics.error(
+            path.field("repo"),
+            format!("repository {repo:?} has to be an https:// or ssh:// URL"),
+        );
+    }
+    if version.as_ref().is_some_and(|v| v.starts_with('-')) {
+        diagnostics.error(path.field("version"), "the version can't start with -");
+    }
+}
+
+fn validate_task(
+    task: &TaskInfo,
+    all_nodes: &[Node],
+    nodes: &HashMap<&str, usize>,
+    path: &JsonPath,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut params = HashSet::new();
+    for (i, param) in task.params.iter().enumerate() {
+        let param_path = path.field("params").index(i);
+        if !params.insert(param.name.as_str()) {
+            diagnostics.error(
+                param_path.field("name"),
+                format!("duplicate parameter {:?}", param.name),
+            );
+        }
+        param.schema.check_schema(&param_path, diagnostics);
+    }
+
+    if let Script::Source(source) = &task.script {
+        validate_source(source, &path.field("script"), diagnostics);
+    }
+    if let Script::Source(Source::Git { path: script, .. }) = &task.script {
+        match script {
+            None => diagnostics.error(
+                path.field("script").field("path"),
+                "the path of the script in the repository is required",
+            ),
+            Some(script)
+                if script.is_absolute()
+                    || script.components().any(|c| c == Component::ParentDir) =>
+            {
+                diagnostics.error(
+                    path.field("script").field("path"),
+                    "the path has to be relative to the repository, without ..",
+                )
+            }
+            Some(_) => {}
+        }
+    }
+
+    if task.timeout == Some(0) {
+        diagnostics.error(path.field("timeout"), "timeout must be greater than 0");
+    }
+    if task.concurrency.per_node == Some(0) {
+        diagnostics.warning(
+            path.field("concurrency").field("per_node"),
+            "a limit of 0 means the task can never run",
+        );
+    }
+
+    if let Some(allowed) = &task.allowed_nodes {
+        if allowed.is_empty() {
+            diagnostics.warning(
+                path.field("allowed_nodes"),
+                "no nodes are allowed, the task can never run",
+            );
+        }
+        for (i, node) in allowed.iter().enumerate() {
+            if !nodes.contains_key(node.as_str()) {
+                diagnostics.error(
+                    path.field("allowed_nodes").index(i),
+                    format!("unknown node {node:?}"),
+                );
+            }
+        }
+    }
+
+    if let Some(disallowed) = &task.disallowed_nodes {
+        for (i, node) in disallowed.iter().enumerate() {
+            let node_path = path.field("disallowed_nodes").index(i);
+            if !nodes.contains_key(node.as_str()) {
+                diagnostics.warning(&node_path, format!("unknown node {node:?}"));
+            }
+            if task
+                .allowed_nodes
+                .as_ref()
+                .is_some_and(|allowed| allowed.contains(node))
+            {
+                diagnostics.error(
+                    &node_path,
+                    format!("node {node:?} is both allowed and disallowed"),
+                );
+            }
+        }
+    }
+
+    if task.allowed_nodes.as_ref().is_none_or(|a| !a.is_empty())
+        && !all_nodes.iter().any(|node| task.can_run_on_node(node))
+    {
+        diagnostics.warning(path, "no configured node can run this task");
+    }
+}
+
+fn validate_fingerprints(fingerprints: &[String], path: &JsonPath, diagnostics: &mut Diagnostics) {
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        let fingerprint = normalize_fingerprint(fingerprint);
+        if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+            diagnostics.error(
+                path.index(i),
+                "not a SHA-256 fingerprint, 64 hex digits optionally separated by :",
+            );
+        }
+    }
+}
+
+fn validate_node(
+    node: &NodeConfig,
+    general: &GeneralConfig,
+    path: &JsonPath,
+    diagnostics: &mut Diagnostics,
+) {
+    if !general.nodes.iter().any(|n| n.name == node.name) {
+        diagnostics.error(
+            path.field("name"),
+            format!("node {:?} is not listed in general.nodes", node.name),
+        );
+    }
+    for (field, file) in [
+        ("ca_file", &node.ca_file),
+        ("cert_file", &node.cert_file),
+        ("key_file", &node.key_file),
+    ] {
+        if !file.is_file() {
+            diagnostics.error(
+                path.field(field),
+                format!("{} does not exist or is not a file", file.display()),
+            );
+        }
+    }
+    for (i, file) in node.crl_files.iter().enumerate() {
+        if !file.is_file() {
+            diagnostics.error(
+                path.field("crl_files").index(i),
+                format!("{} does not exist or is not a file", file.display()),
+            );
+        }
+    }
+    if let Some(file) = &node.cluster_key_file {
+        if !file.is_file() {
+            diagnostics.error(
+                path.field("cluster_key_file"),
+                format!("{} does not exist or is not a file", file.display()),
+            );
+        }
+    }
+
+    if let Some(dir) = &node.ca_dir {
+        if !dir.is_dir() {
+            diagnostics.error(
+                path.field("ca_dir"),
+                format!("{} does not exist or is not a directory", dir.display()),
+            );
+        }
+    }
+
+    if node.listen.is_empty() {
+        diagnostics.error(path.field("listen"), "the node has to listen somewhere");
+    }
+    let mut addresses = HashMap::new();
+    for (field, listen) in [("listen", &node.listen), ("api_listen", &node.api_listen)] {
+        for (i, addr) in listen.iter().enumerate() {
+            let addr_path = path.field(field).index(i);
+            // HTTP and raw TLS both take the TCP port
+            let socket = match addr {
+                ListenAddr::Tcp(socket) | ListenAddr::Tls(socket) => format!("tcp {socket}"),
+                ListenAddr::Quic(socket) => format!("udp {socket}"),
+                ListenAddr::Unix(path) => format!("unix {}", path.display()),
+            };
+            if let Some(first) = addresses.insert(socket, addr_path.clone()) {
+                diagnostics.error(&addr_path, format!("{addr} is already used by {first}"));
+            }
+            if field == "api_listen" && !addr.is_http() {
+                diagnostics.error(&addr_path, "the API is only served over HTTP");
+            }
+        }
+    }
+    if let Some(advertise) = &node.advertise {
+        if let Err(e) = transports(advertise) {
+            diagnostics.error(path.field("advertise"), e.to_string());
+        }
+    }
+    if node.codecs.is_empty() {
+        diagnostics.error(path.field("codecs"), "the node has to offer a codec");
+    }
+    let limits = path.field("limits");
+    if node.limits.max_message_size < ChatterLimits::MIN_MESSAGE_SIZE {
+        diagnostics.error(
+            limits.field("max_message_size"),
+            format!(
+                "max_message_size must be at least {}",
+                ChatterLimits::MIN_MESSAGE_SIZE
+            ),
+        );
+    }
+    if node.limits.handshake_timeout == 0 {
+        diagnostics.error(
+            limits.field("handshake_timeout"),
+            "handshake_timeout must be greater than 0",
+        );
+    }
+    if node.limits.handshakes_per_ip == 0 {
+        diagnostics.error(
+            limits.field("handshakes_per_ip"),
+            "handshakes_per_ip must be greater than 0",
+        );
+    }
+}
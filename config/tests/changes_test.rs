@@ -0,0 +1,41 @@
+use config::{merge, GeneralConfig, Section};
+use diff::Diff;
+use serde_json::json;
+
+/// Config with a task running each (name, script)
+fn general(tasks: &[(&str, &str)]) -> GeneralConfig {
+    let tasks = tasks
+        .iter()
+        .map(|(name, script)| (name.to_string(), json!({"params": [], "script": script})))
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::from_value(json!({
+        "nodes": [{"address": "https://node1.example.com", "name": "node1"}],
+        "tasks": tasks,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn rendered_changes() {
+    let old = general(&[("build", "build.ts"), ("test", "test.ts")]);
+    let new = general(&[("build", "build2.ts"), ("deploy", "deploy.ts")]);
+    assert_eq!(
+        old.diff_changes(&old.diff(&new)).to_string(),
+        "~ task build (script)\n+ task deploy\n- task test"
+    );
+}
+
+#[test]
+fn three_way_merge() {
+    let base = general(&[("build", "build.ts"), ("test", "test.ts")]);
+    let ours = general(&[("build", "build2.ts"), ("test", "test.ts")]);
+    let theirs = general(&[("build", "build.ts")]);
+    let merged = merge(&base, &base.diff(&ours), &base.diff(&theirs)).unwrap();
+    assert_eq!(merged, general(&[("build", "build2.ts")]));
+
+    let conflicting = general(&[("build", "build3.ts")]);
+    let conflicts = merge(&base, &base.diff(&ours), &base.diff(&conflicting)).unwrap_err();
+    assert_eq!(conflicts.0.len(), 1);
+    assert_eq!(conflicts.0[0].section, Section::Task);
+    assert_eq!(conflicts.0[0].name, "build");
+}
@@ -0,0 +1,61 @@
+use config::{migrate, migrate_file, Config, MigrationError, CONFIG_VERSION};
+use serde_json::json;
+
+#[test]
+fn unversioned_config() {
+    let mut value = json!({
+        "$schema": "config.schema.json",
+        "general": {"nodes": [], "tasks": {}},
+        "node": {
+            "ca_file": "ca.crt",
+            "cert_file": "node.crt",
+            "key_file": "node.key",
+            "addr": "127.0.0.1:8080",
+            "name": "node1"
+        }
+    });
+    assert_eq!(migrate(&mut value).unwrap(), 0);
+    assert_eq!(value["version"], CONFIG_VERSION);
+    assert_eq!(value["$schema"], "config.schema.json");
+    assert_eq!(value["node"]["listen"], json!(["127.0.0.1:8080"]));
+    let config = Config::from_json(&value.to_string()).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+
+    assert_eq!(migrate(&mut value).unwrap(), CONFIG_VERSION);
+}
+
+#[test]
+fn newer_config() {
+    let mut value = json!({"version": CONFIG_VERSION + 1});
+    assert!(matches!(
+        migrate(&mut value),
+        Err(MigrationError::TooNew(v)) if v == CONFIG_VERSION + 1
+    ));
+}
+
+#[test]
+fn rewritten_file() {
+    let dir = std::env::temp_dir().join(format!("gilbert-migrate-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.json");
+    let old = json!({
+        "general": {"nodes": [], "tasks": {}},
+        "node": {
+            "ca_file": "ca.crt",
+            "cert_file": "node.crt",
+            "key_file": "node.key",
+            "addr": "127.0.0.1:8080",
+            "name": "node1"
+        }
+    });
+    std::fs::write(&path, old.to_string()).unwrap();
+    assert_eq!(migrate_file(&path).unwrap(), 0);
+    let config = Config::from_json(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+    // Replaced through a temporary file, which doesn't stay behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    assert_eq!(migrate_file(&path).unwrap(), CONFIG_VERSION);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,73 @@
+use config::TaskInfo;
+use serde_json::{json, Value};
+
+fn task() -> TaskInfo {
+    serde_json::from_value(json!({
+        "params": [
+            {"name": "count", "type": "integer", "minimum": 1, "maximum": 10, "default": 3},
+            {"name": "mode", "type": "enum", "values": ["fast", "slow"]},
+            {"name": "label", "type": "string", "pattern": "[a-z]+", "nullable": true, "optional": true},
+            {"name": "tags", "type": "array", "items": {"type": "string", "max_length": 5}},
+            {"name": "opts", "type": "object", "optional": true, "properties": [
+                {"name": "verbose", "type": "boolean", "default": false}
+            ]}
+        ],
+        "script": "job.ts"
+    }))
+    .unwrap()
+}
+
+fn args(value: Value) -> serde_json::Map<String, Value> {
+    match value {
+        Value::Object(obj) => obj,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn defaults_are_filled_in() {
+    let resolved = task()
+        .resolve_params(args(json!({
+            "mode": "fast",
+            "tags": ["a", "b"],
+            "opts": {}
+        })))
+        .unwrap();
+    assert_eq!(
+        resolved,
+        vec![
+            json!(3),
+            json!("fast"),
+            Value::Null,
+            json!(["a", "b"]),
+            json!({"verbose": false})
+        ]
+    );
+}
+
+#[test]
+fn constraints_are_checked() {
+    let errors = task()
+        .resolve_params(args(json!({
+            "count": 11,
+            "mode": "medium",
+            "label": "ABC",
+            "tags": ["ok", "too long"],
+            "other": 1
+        })))
+        .unwrap_err();
+    let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec!["$.count", "$.mode", "$.label", "$.tags[1]", "$.other"]
+    );
+}
+
+#[test]
+fn missing_required_param() {
+    let errors = task()
+        .resolve_params(args(json!({"tags": []})))
+        .unwrap_err();
+    let paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths, vec!["$.mode"]);
+}
@@ -0,0 +1,56 @@
+use std::{collections::HashMap, time::Duration};
+
+use config::{Backoff, Resources};
+
+#[test]
+fn backoff_delays() {
+    let fixed = Backoff::Fixed { delay: 5 };
+    assert_eq!(fixed.delay(0), Duration::from_secs(5));
+    assert_eq!(fixed.delay(3), Duration::from_secs(5));
+
+    let exponential = Backoff::Exponential {
+        initial: 2,
+        max: Some(10),
+    };
+    let delays = (0..5).map(|retry| exponential.delay(retry).as_secs());
+    assert_eq!(delays.collect::<Vec<_>>(), [2, 4, 8, 10, 10]);
+
+    let unbounded = Backoff::Exponential {
+        initial: 1,
+        max: None,
+    };
+    assert_eq!(unbounded.delay(10), Duration::from_secs(1024));
+    assert_eq!(unbounded.delay(200), Duration::from_secs(u64::MAX));
+}
+
+fn resources(cpu: u32, memory: u64, custom: &[(&str, u64)]) -> Resources {
+    Resources {
+        cpu,
+        memory,
+        custom: custom
+            .iter()
+            .map(|(name, x)| (name.to_string(), *x))
+            .collect::<HashMap<_, _>>(),
+    }
+}
+
+#[test]
+fn resources_fit_in_capacity() {
+    let capacity = resources(4, 1024, &[("gpu", 1)]);
+    assert!(Resources::default().fits_in(&capacity));
+    assert!(resources(4, 1024, &[("gpu", 1)]).fits_in(&capacity));
+    assert!(!resources(5, 0, &[]).fits_in(&capacity));
+    assert!(!resources(0, 2048, &[]).fits_in(&capacity));
+    assert!(!resources(0, 0, &[("gpu", 2)]).fits_in(&capacity));
+    // Counters the node doesn't offer are taken as 0
+    assert!(!resources(0, 0, &[("license", 1)]).fits_in(&capacity));
+    assert!(resources(0, 0, &[("license", 0)]).fits_in(&capacity));
+
+    let mut used = resources(3, 512, &[]);
+    used.add(&resources(1, 512, &[("gpu", 1)]));
+    assert!(used.fits_in(&capacity));
+    used.add(&resources(1, 0, &[]));
+    assert!(!used.fits_in(&capacity));
+    used.sub(&resources(2, 2048, &[("gpu", 1)]));
+    assert_eq!(used, resources(3, 0, &[("gpu", 0)]));
+}
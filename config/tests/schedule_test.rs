@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use config::{MissedRuns, Schedule};
+use serde_json::json;
+
+fn schedule(missed: MissedRuns) -> Schedule {
+    let mut schedule: Schedule = serde_json::from_value(json!({
+        "task": "backup",
+        "cron": "0 3 * * *",
+        "timezone": "Europe/Madrid"
+    }))
+    .unwrap();
+    schedule.missed = missed;
+    schedule
+}
+
+fn at(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+#[test]
+fn evaluated_in_time_zone() {
+    let next = schedule(MissedRuns::Skip)
+        .next_after(at("2023-07-01T12:00:00Z"))
+        .unwrap();
+    // Madrid is UTC+2 in summer
+    assert_eq!(next, at("2023-07-02T01:00:00Z"));
+}
+
+#[test]
+fn missed_runs_policy() {
+    let last = at("2023-07-01T01:00:00Z");
+    let now = at("2023-07-04T12:00:00Z");
+    assert!(schedule(MissedRuns::Skip)
+        .missed_runs(last, now)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        schedule(MissedRuns::RunOnce).missed_runs(last, now).unwrap(),
+        vec![at("2023-07-04T01:00:00Z")]
+    );
+    assert_eq!(
+        schedule(MissedRuns::CatchUp).missed_runs(last, now).unwrap(),
+        vec![
+            at("2023-07-02T01:00:00Z"),
+            at("2023-07-03T01:00:00Z"),
+            at("2023-07-04T01:00:00Z")
+        ]
+    );
+}
+
+#[test]
+fn invalid_schedule() {
+    let mut schedule = schedule(MissedRuns::Skip);
+    schedule.timezone = Some("Mars/Olympus".into());
+    assert!(schedule.parse_timezone().is_err());
+    schedule.cron = "61 * * * *".into();
+    assert!(schedule.parse_cron().is_err());
+}
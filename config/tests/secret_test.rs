@@ -0,0 +1,49 @@
+use config::{ClusterKey, SecretRef};
+
+#[test]
+fn encrypted_round_trip() {
+    let key = ClusterKey::from_base64(&ClusterKey::generate()).unwrap();
+    let secret = SecretRef::Encrypted {
+        value: key.encrypt("hunter2"),
+    };
+    let resolved = secret.resolve(Some(&key)).unwrap();
+    assert_eq!(resolved.expose(), "hunter2");
+    assert!(!format!("{resolved:?}").contains("hunter2"));
+
+    let other = ClusterKey::from_base64(&ClusterKey::generate()).unwrap();
+    assert!(secret.resolve(Some(&other)).is_err());
+    assert!(secret.resolve(None).is_err());
+}
+
+#[test]
+fn key_file_round_trip() {
+    let dir = std::env::temp_dir().join(format!("gilbert-key-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cluster.key");
+    let key = ClusterKey::create(&path).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    // The secrets encrypted with the key would be lost if it was replaced
+    assert!(ClusterKey::create(&path).is_err());
+
+    let secret = SecretRef::Encrypted {
+        value: key.encrypt("hunter2"),
+    };
+    let loaded = ClusterKey::load(&path).unwrap();
+    assert_eq!(secret.resolve(Some(&loaded)).unwrap().expose(), "hunter2");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn env_secret() {
+    std::env::set_var("GILBERT_TEST_SECRET", "value");
+    let secret = SecretRef::Env {
+        var: "GILBERT_TEST_SECRET".into(),
+    };
+    assert_eq!(secret.resolve(None).unwrap().expose(), "value");
+}
@@ -0,0 +1,31 @@
+use config::{transports, Transport, TransportError};
+use url::Url;
+
+#[test]
+fn address_transports() {
+    let address = Url::parse("https+quic://node1:8443").unwrap();
+    assert_eq!(
+        transports(&address).unwrap(),
+        [Transport::Quic, Transport::Https]
+    );
+    assert_eq!(
+        Transport::Quic.address(&address).as_str(),
+        "quic://node1:8443"
+    );
+    assert_eq!(
+        Transport::Https.address(&address).as_str(),
+        "https://node1:8443/"
+    );
+
+    let parse = |s| transports(&Url::parse(s).unwrap());
+    assert_eq!(parse("https://node1").unwrap(), [Transport::Https]);
+    assert_eq!(parse("quic+tls://node1"), Err(TransportError::NoPort));
+    assert_eq!(
+        parse("tls+http://node1:8080"),
+        Err(TransportError::SharedPort(Transport::Tls, Transport::Http))
+    );
+    assert_eq!(
+        parse("ws://node1:8080"),
+        Err(TransportError::Unknown("ws".to_string()))
+    );
+}
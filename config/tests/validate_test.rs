@@ -0,0 +1,128 @@
+use config::{Config, Severity};
+use serde_json::json;
+
+fn config(general: serde_json::Value) -> Config {
+    serde_json::from_value(json!({
+        "general": general,
+        "node": {
+            "ca_file": "Cargo.toml",
+            "cert_file": "Cargo.toml",
+            "key_file": "Cargo.toml",
+            "listen": ["127.0.0.1:8080"],
+            "name": "node1"
+        }
+    }))
+    .unwrap()
+}
+
+#[test]
+fn valid_config() {
+    let config = config(json!({
+        "nodes": [{"address": "https://node1.example.com", "name": "node1"}],
+        "tasks": {
+            "job1": {
+                "params": [{"name": "x", "type": "number"}],
+                "script": "Cargo.toml"
+            }
+        }
+    }));
+    assert!(config.validate().is_empty());
+}
+
+#[test]
+fn inconsistent_config() {
+    let config = config(json!({
+        "nodes": [
+            {
+                "address": "https://node2.example.com",
+                "name": "node2",
+                "fingerprints": ["AB:CD", "ab".repeat(32)]
+            },
+            {"address": "https://node3.example.com", "name": "node2"}
+        ],
+        "tasks": {
+            "job1": {
+                "params": [{"name": "x", "type": "number"}, {"name": "x", "type": "string"}],
+                "allowed_nodes": ["node2", "node4"],
+                "disallowed_nodes": ["node2"],
+                "script": "missing.job.ts"
+            }
+        }
+    }));
+    let diagnostics = config.validate();
+    assert!(diagnostics.has_errors());
+    let errors = diagnostics
+        .errors()
+        .map(|d| d.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        vec![
+            "$.general.nodes[0].fingerprints[0]",
+            "$.general.nodes[1].name",
+            "$.general.tasks.job1.params[1].name",
+            "$.general.tasks.job1.allowed_nodes[1]",
+            "$.general.tasks.job1.disallowed_nodes[0]",
+            "$.node.name",
+        ]
+    );
+    assert!(diagnostics
+        .iter()
+        .all(|d| d.severity == Severity::Error || d.path.starts_with("$.general.tasks.job1")));
+}
+
+#[test]
+fn node_names() {
+    let config = config(json!({
+        "nodes": [
+            {"address": "https://node1.example.com", "name": "node1"},
+            {"address": "https://node2.example.com", "name": "node 2"}
+        ],
+        "tasks": {}
+    }));
+    let diagnostics = config.validate();
+    let errors = diagnostics
+        .errors()
+        .map(|d| d.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(errors, ["$.general.nodes[1].name"]);
+}
+
+#[test]
+fn git_sources() {
+    let config = config(json!({
+        "nodes": [{"address": "https://node1.example.com", "name": "node1"}],
+        "tasks": {
+            "job1": {
+                "params": [],
+                "script": {"source": "git", "repo": "/srv/jobs", "path": "job.js"}
+            },
+            "job2": {
+                "params": [],
+                "script": {
+                    "source": "git",
+                    "repo": "https://example.com/jobs.git",
+                    "version": "--output=x",
+                    "path": "job.js"
+                }
+            }
+        },
+        "repositories": {
+            "plugins": {"source": "git", "repo": "file:///srv/plugins"},
+            "remote": {"source": "git", "repo": "ssh://git@example.com/plugins.git"}
+        }
+    }));
+    let diagnostics = config.validate();
+    let errors = diagnostics
+        .errors()
+        .map(|d| d.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        vec![
+            "$.general.tasks.job1.script.repo",
+            "$.general.tasks.job2.script.version",
+            "$.general.repositories.plugins.repo",
+        ]
+    );
+}
@@ -6,6 +6,12 @@ use semver::Version;
 use thiserror::Error;
 use tokio_util::codec::{FramedWrite, LinesCodec, FramedRead, LinesCodecError};
 
+mod lock;
+mod resolve;
+
+pub use lock::{check_locked, install_locked, VerifyError};
+pub use resolve::{Artifact, ArtifactKind, ResolveError, Resolver};
+
 #[derive(Debug, Error)]
 pub enum PluginLoadError {
     #[error(transparent)]
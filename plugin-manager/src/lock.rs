@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use config::{LockedArtifact, LockedPlugin, Lockfile, Plugin, Source, WASM_ARTIFACT};
+use semver::{Version, VersionReq};
+use source_cache::{sha256_file, FetchError, SourceCache};
+use target_lexicon::Triple;
+use thiserror::Error;
+
+use crate::resolve::{locate, ResolveError, Resolver};
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("plugin {0} is not in the lockfile")]
+    NotLocked(String),
+    #[error("the lockfile has plugin {0}, which the config doesn't")]
+    NotConfigured(String),
+    #[error("plugin {plugin} is locked from repository {locked}, the config has {configured}")]
+    RepositoryMismatch {
+        plugin: String,
+        locked: String,
+        configured: String,
+    },
+    #[error("plugin {plugin} is locked at {locked}, which doesn't match {requirement}")]
+    VersionMismatch {
+        plugin: String,
+        locked: String,
+        requirement: String,
+    },
+    #[error("invalid version requirement {0:?}: {1}")]
+    InvalidRequirement(String, semver::Error),
+    #[error("plugin {plugin} has no locked build for {host}")]
+    NoArtifact { plugin: String, host: Triple },
+    #[error("plugin {plugin} doesn't match the lockfile, expected hash {expected}, got {actual}")]
+    HashMismatch {
+        plugin: String,
+        expected: String,
+        actual: String,
+    },
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Resolver<'_> {
+    /// Resolves every plugin to exact versions, hashing all of their builds
+    pub async fn lock(&mut self, plugins: &[Plugin]) -> Result<Lockfile, ResolveError> {
+        let mut lockfile = Lockfile::new();
+        for plugin in plugins {
+            let found = self.manifest(plugin).await?;
+            let mut locations = found
+                .manifest
+                .binaries
+                .iter()
+                .map(|(triple, location)| (triple.to_string(), location))
+                .collect::<Vec<_>>();
+            if let Some(wasm) = &found.manifest.wasm_base {
+                locations.push((WASM_ARTIFACT.to_string(), wasm));
+            }
+            let mut artifacts = BTreeMap::new();
+            for (key, location) in locations {
+                let source = self.cache.pin(&locate(&found.source, location)?).await?;
+                let sha256 = sha256_file(self.cache.fetch(&source).await?).await?;
+                artifacts.insert(key, LockedArtifact { source, sha256 });
+            }
+            let version = match &found.source {
+                Source::Git { version, .. } => version.clone(),
+                _ => None,
+            };
+            lockfile.plugins.insert(
+                found.name,
+                LockedPlugin {
+                    repository: found.repository,
+                    version,
+                    tag: found.tag,
+                    source: found.source,
+                    args: found.manifest.args,
+                    artifacts,
+                },
+            );
+        }
+        Ok(lockfile)
+    }
+}
+
+/// Checks the lockfile locks exactly the plugins of the config, from the repositories it names
+/// and at versions matching their requirements
+pub fn check_locked(lockfile: &Lockfile, plugins: &[Plugin]) -> Result<(), VerifyError> {
+    for plugin in plugins {
+        let locked = lockfile
+            .plugins
+            .get(plugin.name())
+            .ok_or_else(|| VerifyError::NotLocked(plugin.name().to_string()))?;
+        if let Plugin::NameWithRepo { name, repo, .. } = plugin {
+            if *repo != locked.repository {
+                return Err(VerifyError::RepositoryMismatch {
+                    plugin: name.clone(),
+                    locked: locked.repository.clone(),
+                    configured: repo.clone(),
+                });
+            }
+        }
+        let requirement = match plugin {
+            Plugin::NameAndVersion { version, .. } => Some(version),
+            Plugin::NameWithRepo { version, .. } => version.as_ref(),
+            Plugin::Name(_) => None,
+        };
+        if let Some(requirement) = requirement {
+            check_version(plugin.name(), locked, requirement)?;
+        }
+    }
+    if let Some(name) = lockfile
+        .plugins
+        .keys()
+        .find(|name| !plugins.iter().any(|plugin| plugin.name() == *name))
+    {
+        return Err(VerifyError::NotConfigured(name.clone()));
+    }
+    Ok(())
+}
+
+/// Checks the plugin was locked at a tag matching the requirement, as [`Resolver::lock`] does
+fn check_version(
+    plugin: &str,
+    locked: &LockedPlugin,
+    requirement: &str,
+) -> Result<(), VerifyError> {
+    let req = VersionReq::parse(requirement)
+        .map_err(|e| VerifyError::InvalidRequirement(requirement.to_string(), e))?;
+    let version = locked
+        .tag
+        .as_deref()
+        .and_then(|tag| Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok());
+    if version.is_some_and(|version| req.matches(&version)) {
+        return Ok(());
+    }
+    Err(VerifyError::VersionMismatch {
+        plugin: plugin.to_string(),
+        locked: locked.tag.clone().unwrap_or_else(|| "no tag".to_string()),
+        requirement: requirement.to_string(),
+    })
+}
+
+/// Fetches the locked build of the plugin for the host, checking it against the lockfile's hash
+pub async fn install_locked(
+    lockfile: &Lockfile,
+    plugin: &str,
+    cache: &SourceCache,
+    host: &Triple,
+) -> Result<PathBuf, VerifyError> {
+    let locked = lockfile
+        .plugins
+        .get(plugin)
+        .ok_or_else(|| VerifyError::NotLocked(plugin.to_string()))?;
+    let artifact = locked
+        .artifact_for(host)
+        .ok_or_else(|| VerifyError::NoArtifact {
+            plugin: plugin.to_string(),
+            host: host.clone(),
+        })?;
+    let path = cache.fetch(&artifact.source).await?;
+    let actual = sha256_file(&path).await?;
+    if actual != artifact.sha256 {
+        return Err(VerifyError::HashMismatch {
+            plugin: plugin.to_string(),
+            expected: artifact.sha256.clone(),
+            actual,
+        });
+    }
+    Ok(path)
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+};
+
+use config::{
+    repo::{Plugin as Manifest, Repository},
+    Plugin, Source,
+};
+use semver::{Version, VersionReq};
+use source_cache::{FetchError, SourceCache};
+use target_lexicon::Triple;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("unknown repository {0}")]
+    UnknownRepository(String),
+    #[error("plugin {0} is not in any repository")]
+    NotFound(String),
+    #[error("plugin {plugin} is in more than one repository: {}", .repositories.join(", "))]
+    Ambiguous {
+        plugin: String,
+        repositories: Vec<String>,
+    },
+    #[error("plugin {0} asks for a version, but only git sources have versions")]
+    VersionUnsupported(String),
+    #[error("invalid version requirement {0:?}: {1}")]
+    InvalidRequirement(String, semver::Error),
+    #[error("plugin {plugin} has no tagged version matching {requirement}")]
+    NoMatchingVersion { plugin: String, requirement: String },
+    #[error("plugin {plugin} has no binary for {host} nor a wasm build")]
+    NoArtifact { plugin: String, host: Triple },
+    #[error("invalid location {0}")]
+    InvalidLocation(String),
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Native(Triple),
+    Wasm,
+}
+
+/// Concrete build of a plugin to install
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub plugin: String,
+    pub repository: String,
+    pub kind: ArtifactKind,
+    pub source: Source,
+    pub args: Vec<String>,
+}
+
+pub(crate) struct Found {
+    pub name: String,
+    pub repository: String,
+    pub source: Source,
+    /// Tag the version requirement resolved to
+    pub tag: Option<String>,
+    pub manifest: Manifest,
+}
+
+/// Looks plugins up in the configured repositories, caching the repository manifests it reads
+pub struct Resolver<'a> {
+    repositories: &'a HashMap<String, Source>,
+    pub(crate) cache: &'a SourceCache,
+    host: Triple,
+    manifests: HashMap<String, Repository>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(repositories: &'a HashMap<String, Source>, cache: &'a SourceCache) -> Self {
+        Self {
+            repositories,
+            cache,
+            host: Triple::host(),
+            manifests: HashMap::new(),
+        }
+    }
+
+    /// Resolves for another target than the one this was compiled for
+    pub fn with_host(mut self, host: Triple) -> Self {
+        self.host = host;
+        self
+    }
+
+    pub async fn resolve(&mut self, plugin: &Plugin) -> Result<Artifact, ResolveError> {
+        let found = self.manifest(plugin).await?;
+        let (kind, location) = match found.manifest.binaries.get(&self.host) {
+            Some(binary) => (ArtifactKind::Native(self.host.clone()), binary),
+            None => (
+                ArtifactKind::Wasm,
+                found
+                    .manifest
+                    .wasm_base
+                    .as_ref()
+                    .ok_or_else(|| ResolveError::NoArtifact {
+                        plugin: found.name.clone(),
+                        host: self.host.clone(),
+                    })?,
+            ),
+        };
+        Ok(Artifact {
+            source: locate(&found.source, location)?,
+            plugin: found.name,
+            repository: found.repository,
+            kind,
+            args: found.manifest.args,
+        })
+    }
+
+    /// Finds the plugin's manifest, with its source pinned to an exact version
+    pub(crate) async fn manifest(&mut self, plugin: &Plugin) -> Result<Found, ResolveError> {
+        let (name, repository, version) = match plugin {
+            Plugin::Name(name) => (name, None, None),
+            Plugin::NameAndVersion { name, version } => (name, None, Some(version)),
+            Plugin::NameWithRepo {
+                name,
+                repo,
+                version,
+            } => (name, Some(repo), version.as_ref()),
+        };
+        let repository = match repository {
+            Some(repo) => repo.clone(),
+            None => self.find(name).await?,
+        };
+        let source = self
+            .repository(&repository)
+            .await?
+            .plugins
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ResolveError::NotFound(name.clone()))?;
+        let (source, tag) = match version {
+            Some(version) => {
+                let (source, tag) = self.with_version(name, source, version).await?;
+                (source, Some(tag))
+            }
+            None => (source, None),
+        };
+        let source = self.cache.pin(&source).await?;
+        Ok(Found {
+            name: name.clone(),
+            repository,
+            manifest: self.read(&source).await?,
+            source,
+            tag,
+        })
+    }
+
+    /// Name of the only repository that has the plugin
+    async fn find(&mut self, plugin: &str) -> Result<String, ResolveError> {
+        let mut names = self.repositories.keys().cloned().collect::<Vec<_>>();
+        names.sort_unstable();
+        let mut found = Vec::new();
+        for name in names {
+            if self.repository(&name).await?.plugins.contains_key(plugin) {
+                found.push(name);
+            }
+        }
+        match found.len() {
+            0 => Err(ResolveError::NotFound(plugin.to_string())),
+            1 => Ok(found.remove(0)),
+            _ => Err(ResolveError::Ambiguous {
+                plugin: plugin.to_string(),
+                repositories: found,
+            }),
+        }
+    }
+
+    async fn repository(&mut self, name: &str) -> Result<&Repository, ResolveError> {
+        if !self.manifests.contains_key(name) {
+            let source = self
+                .repositories
+                .get(name)
+                .ok_or_else(|| ResolveError::UnknownRepository(name.to_string()))?;
+            let repository = self.read(source).await?;
+            self.manifests.insert(name.to_string(), repository);
+        }
+        Ok(&self.manifests[name])
+    }
+
+    /// Git source at the highest tag matching the requirement, tags being like `1.2.0` or `v1.2.0`,
+    /// and the tag
+    async fn with_version(
+        &self,
+        plugin: &str,
+        source: Source,
+        requirement: &str,
+    ) -> Result<(Source, String), ResolveError> {
+        let Source::Git { repo, path, .. } = source else {
+            return Err(ResolveError::VersionUnsupported(plugin.to_string()));
+        };
+        let req = VersionReq::parse(requirement)
+            .map_err(|e| ResolveError::InvalidRequirement(requirement.to_string(), e))?;
+        let tag = self
+            .cache
+            .tags(&repo)
+            .await?
+            .into_iter()
+            .filter_map(|tag| {
+                let version = Version::parse(tag.strip_prefix('v').unwrap_or(&tag)).ok()?;
+                req.matches(&version).then_some((version, tag))
+            })
+            .max()
+            .map(|(_, tag)| tag)
+            .ok_or_else(|| ResolveError::NoMatchingVersion {
+                plugin: plugin.to_string(),
+                requirement: requirement.to_string(),
+            })?;
+        let source = Source::Git {
+            repo,
+            version: Some(format!("refs/tags/{tag}")),
+            path,
+        };
+        Ok((source, tag))
+    }
+
+    async fn read<T: serde::de::DeserializeOwned>(
+        &self,
+        source: &Source,
+    ) -> Result<T, ResolveError> {
+        let path = self.cache.fetch(source).await?;
+        let contents = tokio::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Source of a location given in a manifest, either a URL or a path relative to the manifest
+pub(crate) fn locate(manifest: &Source, location: &str) -> Result<Source, ResolveError> {
+    if let Ok(url) = Url::parse(location) {
+        return Ok(Source::Web { url });
+    }
+    let relative = Path::new(location);
+    if relative.is_absolute() || relative.components().any(|c| c == Component::ParentDir) {
+        return Err(ResolveError::InvalidLocation(location.to_string()));
+    }
+    let sibling = |path: &Path| path.parent().unwrap_or(Path::new("")).join(relative);
+    Ok(match manifest {
+        Source::Fs { path } => Source::Fs {
+            path: sibling(path),
+        },
+        Source::Git {
+            repo,
+            version,
+            path,
+        } => Source::Git {
+            repo: repo.clone(),
+            version: version.clone(),
+            path: Some(sibling(path.as_deref().unwrap_or(Path::new("")))),
+        },
+        Source::Web { url } => Source::Web {
+            url: url
+                .join(location)
+                .map_err(|_| ResolveError::InvalidLocation(location.to_string()))?,
+        },
+    })
+}
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, path::PathBuf, process::Command};
+
+use config::{Plugin, Source};
+use plugin_manager::{
+    check_locked, install_locked, ArtifactKind, ResolveError, Resolver, VerifyError,
+};
+use serde_json::json;
+use source_cache::SourceCache;
+use target_lexicon::Triple;
+
+fn setup(name: &str) -> (PathBuf, HashMap<String, Source>) {
+    let root = std::env::temp_dir().join(format!("gilbert-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("docker/bin")).unwrap();
+    std::fs::write(
+        root.join("repo.json"),
+        json!({"plugins": {"docker": {"source": "fs", "path": root.join("docker/plugin.json")}}})
+            .to_string(),
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("docker/plugin.json"),
+        json!({
+            "binaries": {"x86_64-unknown-linux-gnu": "bin/docker"},
+            "wasm_base": "docker.wasm",
+            "args": ["--quiet"]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    std::fs::write(root.join("docker/bin/docker"), "native").unwrap();
+    std::fs::write(root.join("docker/docker.wasm"), "wasm").unwrap();
+    let repositories = HashMap::from([(
+        "main".to_string(),
+        Source::Fs {
+            path: root.join("repo.json"),
+        },
+    )]);
+    (root, repositories)
+}
+
+#[tokio::test]
+async fn native_binary_or_wasm() {
+    let (root, repositories) = setup("resolve");
+    let cache = SourceCache::new(root.join("cache"));
+    let plugin = Plugin::Name("docker".into());
+
+    let linux: Triple = "x86_64-unknown-linux-gnu".parse().unwrap();
+    let artifact = Resolver::new(&repositories, &cache)
+        .with_host(linux.clone())
+        .resolve(&plugin)
+        .await
+        .unwrap();
+    assert_eq!(artifact.repository, "main");
+    assert_eq!(artifact.kind, ArtifactKind::Native(linux));
+    assert_eq!(
+        artifact.source,
+        Source::Fs {
+            path: root.join("docker/bin/docker")
+        }
+    );
+    assert_eq!(artifact.args, vec!["--quiet"]);
+
+    let artifact = Resolver::new(&repositories, &cache)
+        .with_host("aarch64-apple-darwin".parse().unwrap())
+        .resolve(&plugin)
+        .await
+        .unwrap();
+    assert_eq!(artifact.kind, ArtifactKind::Wasm);
+
+    let missing = Resolver::new(&repositories, &cache)
+        .resolve(&Plugin::Name("podman".into()))
+        .await;
+    assert!(matches!(missing, Err(ResolveError::NotFound(_))));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn installs_are_checked_against_the_lockfile() {
+    let (root, repositories) = setup("lock");
+    let cache = SourceCache::new(root.join("cache"));
+    let lockfile = Resolver::new(&repositories, &cache)
+        .lock(&[Plugin::Name("docker".into())])
+        .await
+        .unwrap();
+    assert_eq!(lockfile.plugins["docker"].artifacts.len(), 2);
+
+    check_locked(&lockfile, &[Plugin::Name("docker".into())]).unwrap();
+    assert!(matches!(
+        check_locked(&lockfile, &[]),
+        Err(VerifyError::NotConfigured(_))
+    ));
+    let plugins = [Plugin::Name("docker".into()), Plugin::Name("k8s".into())];
+    assert!(matches!(
+        check_locked(&lockfile, &plugins),
+        Err(VerifyError::NotLocked(_))
+    ));
+    let plugins = [Plugin::NameWithRepo {
+        name: "docker".into(),
+        repo: "other".into(),
+        version: None,
+    }];
+    assert!(matches!(
+        check_locked(&lockfile, &plugins),
+        Err(VerifyError::RepositoryMismatch { .. })
+    ));
+
+    let linux: Triple = "x86_64-unknown-linux-gnu".parse().unwrap();
+    let path = install_locked(&lockfile, "docker", &cache, &linux)
+        .await
+        .unwrap();
+    assert_eq!(path, root.join("docker/bin/docker"));
+
+    std::fs::write(root.join("docker/bin/docker"), "tampered").unwrap();
+    let tampered = install_locked(&lockfile, "docker", &cache, &linux).await;
+    assert!(matches!(tampered, Err(VerifyError::HashMismatch { .. })));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn versions_resolve_to_the_highest_matching_tag() {
+    let root = std::env::temp_dir().join(format!("gilbert-versions-{}", std::process::id()));
+    let repo = root.join("docker");
+    std::fs::create_dir_all(&repo).unwrap();
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+    };
+    git(&["init", "-q"]);
+    std::fs::write(repo.join("docker.wasm"), "wasm").unwrap();
+    for tag in ["v1.0.0", "v1.2.0", "1.3.0-beta.1", "v2.0.0", "latest"] {
+        std::fs::write(
+            repo.join("plugin.json"),
+            json!({"binaries": {}, "wasm_base": "docker.wasm", "args": [tag]}).to_string(),
+        )
+        .unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", tag]);
+        git(&["tag", tag]);
+    }
+    std::fs::write(
+        root.join("repo.json"),
+        json!({"plugins": {"docker": {
+            "source": "git",
+            "repo": repo.to_string_lossy(),
+            "path": "plugin.json"
+        }}})
+        .to_string(),
+    )
+    .unwrap();
+    let repositories = HashMap::from([(
+        "main".to_string(),
+        Source::Fs {
+            path: root.join("repo.json"),
+        },
+    )]);
+    let cache = SourceCache::new(root.join("cache"));
+    let mut resolver = Resolver::new(&repositories, &cache);
+    let plugin = |version: &str| Plugin::NameAndVersion {
+        name: "docker".into(),
+        version: version.into(),
+    };
+
+    let lockfile = resolver.lock(&[plugin("^1")]).await.unwrap();
+    assert_eq!(lockfile.plugins["docker"].args, ["v1.2.0"]);
+    check_locked(&lockfile, &[plugin("^1.1")]).unwrap();
+    // A lock from before the requirement changed is stale
+    assert!(matches!(
+        check_locked(&lockfile, &[plugin("^2")]),
+        Err(VerifyError::VersionMismatch { .. })
+    ));
+    let lockfile = resolver.lock(&[plugin(">=1.1, <1.3")]).await.unwrap();
+    assert_eq!(lockfile.plugins["docker"].args, ["v1.2.0"]);
+    assert!(matches!(
+        resolver.lock(&[plugin("^3")]).await,
+        Err(ResolveError::NoMatchingVersion { .. })
+    ));
+    assert!(matches!(
+        resolver.lock(&[plugin("latest")]).await,
+        Err(ResolveError::InvalidRequirement(..))
+    ));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
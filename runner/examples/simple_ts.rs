@@ -15,6 +15,8 @@ async fn main() {
         .unwrap(),
         printer: SimplePrinter,
         params: vec![json!(1), json!(2)],
+        timeout: None,
+        secrets: Default::default(),
     })
     .await
     .unwrap()
@@ -0,0 +1,206 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use config::{ClusterKey, OnLimit, Resources, SecretError, TaskInfo};
+use deno_core::ModuleSpecifier;
+use source_cache::{FetchError, SourceCache};
+use thiserror::Error;
+use tokio::sync::Notify;
+
+use crate::{Printer, RunParams};
+
+#[derive(Debug, Error)]
+pub enum ExecutionError {
+    #[error("Task {0} is at its concurrency limit")]
+    ConcurrencyLimit(String),
+    #[error("Task {0} requests more resources than the node has")]
+    InsufficientResources(String),
+    #[error("Unable to fetch the script: {0}")]
+    Fetch(#[from] FetchError),
+    #[error("Invalid script {0}")]
+    InvalidScript(PathBuf),
+    #[error("Unable to resolve secret {name}: {error}")]
+    Secret { name: String, error: SecretError },
+    #[error("Job failed after {attempts} attempts: {error}")]
+    Failed { attempts: u32, error: String },
+}
+
+/// Runs jobs in this node, enforcing the execution policy of their tasks
+pub struct Executor {
+    /// Resources of the node, `None` if they aren't limited
+    capacity: Option<Resources>,
+    cluster_key: Option<ClusterKey>,
+    sources: SourceCache,
+    state: Mutex<ExecutorState>,
+    released: Notify,
+}
+
+#[derive(Default)]
+struct ExecutorState {
+    running: HashMap<String, u32>,
+    used: Resources,
+}
+
+enum Blocked {
+    Concurrency,
+    Resources,
+}
+
+/// Slot for a running job, given back when dropped
+struct Reservation<'a> {
+    executor: &'a Executor,
+    task: String,
+    resources: Resources,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.executor.state.lock().unwrap();
+            if let Some(running) = state.running.get_mut(&self.task) {
+                *running -= 1;
+            }
+            state.used.sub(&self.resources);
+        }
+        self.executor.released.notify_waiters();
+    }
+}
+
+impl Executor {
+    pub fn new(
+        capacity: Option<Resources>,
+        cluster_key: Option<ClusterKey>,
+        sources: SourceCache,
+    ) -> Self {
+        Self {
+            capacity,
+            cluster_key,
+            sources,
+            state: Mutex::default(),
+            released: Notify::new(),
+        }
+    }
+
+    /// Number of jobs of each task running right now
+    pub fn running(&self) -> HashMap<String, u32> {
+        self.state.lock().unwrap().running.clone()
+    }
+
+    fn try_reserve(&self, name: &str, task: &TaskInfo) -> Result<Reservation<'_>, Blocked> {
+        let mut state = self.state.lock().unwrap();
+        let running = state.running.get(name).copied().unwrap_or(0);
+        if task
+            .concurrency
+            .per_node
+            .is_some_and(|limit| running >= limit)
+        {
+            return Err(Blocked::Concurrency);
+        }
+        if let Some(capacity) = &self.capacity {
+            let mut used = state.used.clone();
+            used.add(&task.resources);
+            if !used.fits_in(capacity) {
+                return Err(Blocked::Resources);
+            }
+        }
+        *state.running.entry(name.to_string()).or_default() += 1;
+        state.used.add(&task.resources);
+        Ok(Reservation {
+            executor: self,
+            task: name.to_string(),
+            resources: task.resources.clone(),
+        })
+    }
+
+    async fn reserve(
+        &self,
+        name: &str,
+        task: &TaskInfo,
+    ) -> Result<Reservation<'_>, ExecutionError> {
+        if let Some(capacity) = &self.capacity {
+            if !task.resources.fits_in(capacity) {
+                return Err(ExecutionError::InsufficientResources(name.to_string()));
+            }
+        }
+        loop {
+            // Created before checking, so a release in between isn't missed
+            let released = self.released.notified();
+            match self.try_reserve(name, task) {
+                Ok(reservation) => return Ok(reservation),
+                Err(Blocked::Concurrency) if task.concurrency.on_limit == OnLimit::Reject => {
+                    return Err(ExecutionError::ConcurrencyLimit(name.to_string()))
+                }
+                Err(_) => released.await,
+            }
+        }
+    }
+
+    /// Runs a job of the task, waiting for a free slot and retrying it according to the task's policy.
+    /// Every attempt gets its own clone of the printer
+    pub async fn execute<P>(
+        &self,
+        name: &str,
+        task: &TaskInfo,
+        params: Vec<serde_json::Value>,
+        printer: P,
+    ) -> Result<(), ExecutionError>
+    where
+        P: Printer + Clone + Send + 'static,
+    {
+        let script = self.sources.script(&task.script).await?;
+        let main_module = script
+            .canonicalize()
+            .ok()
+            .and_then(|path| ModuleSpecifier::from_file_path(path).ok())
+            .ok_or(ExecutionError::InvalidScript(script))?;
+        let secrets = task
+            .secrets
+            .iter()
+            .map(|(name, secret)| {
+                secret
+                    .resolve(self.cluster_key.as_ref())
+                    .map(|value| (name.clone(), value))
+                    .map_err(|error| ExecutionError::Secret {
+                        name: name.clone(),
+                        error,
+                    })
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        let _reservation = self.reserve(name, task).await?;
+        let mut retry = 0;
+        loop {
+            let params = RunParams {
+                main_module: main_module.clone(),
+                printer: printer.clone(),
+                params: params.clone(),
+                timeout: task.timeout(),
+                secrets: secrets.clone(),
+            };
+            match run_blocking(params).await {
+                Ok(()) => return Ok(()),
+                Err(_) if retry < task.max_retries => {
+                    tokio::time::sleep(task.backoff.delay(retry)).await;
+                    retry += 1;
+                }
+                Err(error) => {
+                    return Err(ExecutionError::Failed {
+                        attempts: retry + 1,
+                        error,
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// Runs the job in its own thread, as the JS runtime can't be sent between threads
+async fn run_blocking<P: Printer + Send + 'static>(params: RunParams<P>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+        rt.block_on(crate::run(params)).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
@@ -1,5 +1,15 @@
-use std::{rc::Rc, sync::Arc};
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::Duration,
+};
 
+use config::Secret;
 use deno_core::v8::{HandleScope, Value};
 use deno_runtime::{
     deno_core::{error::AnyError, ModuleSpecifier},
@@ -12,9 +22,11 @@ use print_ext::print_extension;
 use thiserror::Error;
 
 // mod deno_module_loader;
+mod executor;
 mod module_loader;
 mod print_ext;
 
+pub use executor::{ExecutionError, Executor};
 pub use print_ext::{Printer, SimplePrinter};
 
 fn serde_json_value_to_v8<'a>(
@@ -66,6 +78,8 @@ pub enum RunnerError {
     SerdeV8Error(#[from] deno_core::serde_v8::Error),
     #[error("Undecodeable args: {0:?}")]
     UndecodeableArgs(Vec<UndecodeableArg>),
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
 }
 
 #[derive(Debug, Clone)]
@@ -84,6 +98,35 @@ pub struct RunParams<P: Printer + 'static> {
     pub main_module: ModuleSpecifier,
     pub printer: P,
     pub params: Vec<serde_json::Value>,
+    /// Stops the job if it runs for longer than this
+    pub timeout: Option<Duration>,
+    /// Exposed to the script as the frozen global `secrets` object
+    pub secrets: HashMap<String, Secret>,
+}
+
+/// Terminates the JS execution of an isolate if it isn't dropped before the timeout
+struct Watchdog {
+    _done: mpsc::Sender<()>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn start(handle: v8::IsolateHandle, timeout: Duration) -> Self {
+        let (done, rx) = mpsc::channel::<()>();
+        let fired = Arc::new(AtomicBool::new(false));
+        let fired_thread = fired.clone();
+        std::thread::spawn(move || {
+            if rx.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                fired_thread.store(true, Ordering::SeqCst);
+                handle.terminate_execution();
+            }
+        });
+        Self { _done: done, fired }
+    }
+
+    fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
 }
 
 #[allow(clippy::future_not_send)]
@@ -98,7 +141,41 @@ pub async fn run<P: Printer + 'static>(params: RunParams<P>) -> Result<(), Runne
             ..Default::default()
         },
     );
-    let main_module = worker.preload_main_module(&main_module).await?;
+    set_secrets(&mut worker, &params.secrets);
+    let Some(timeout) = params.timeout else {
+        return run_worker(&mut worker, &main_module, &params.params).await;
+    };
+    // The watchdog stops busy JS code, the timeout a job waiting on the event loop
+    let watchdog = Watchdog::start(worker.js_runtime.v8_isolate().thread_safe_handle(), timeout);
+    match tokio::time::timeout(timeout, run_worker(&mut worker, &main_module, &params.params)).await
+    {
+        Ok(_) if watchdog.fired() => Err(RunnerError::Timeout(timeout)),
+        Ok(res) => res,
+        Err(_) => Err(RunnerError::Timeout(timeout)),
+    }
+}
+
+fn set_secrets(worker: &mut MainWorker, secrets: &HashMap<String, Secret>) {
+    let scope = &mut worker.js_runtime.handle_scope();
+    let obj = v8::Object::new(scope);
+    for (name, secret) in secrets {
+        let key = v8::String::new(scope, name).unwrap();
+        let value = v8::String::new(scope, secret.expose()).unwrap();
+        obj.set(scope, key.into(), value.into());
+    }
+    obj.set_integrity_level(scope, v8::IntegrityLevel::Frozen);
+    let global = scope.get_current_context().global(scope);
+    let name = v8::String::new(scope, "secrets").unwrap();
+    global.set(scope, name.into(), obj.into());
+}
+
+#[allow(clippy::future_not_send)]
+async fn run_worker(
+    worker: &mut MainWorker,
+    main_module: &ModuleSpecifier,
+    params: &[serde_json::Value],
+) -> Result<(), RunnerError> {
+    let main_module = worker.preload_main_module(main_module).await?;
     worker.evaluate_module(main_module).await?;
     worker.run_event_loop(false).await?;
     let global = worker.js_runtime.get_module_namespace(main_module)?;
@@ -141,7 +218,6 @@ pub async fn run<P: Printer + 'static>(params: RunParams<P>) -> Result<(), Runne
             let recv = Local::new(scope, &global);
             let mut errors = Vec::new();
             let args = params
-                .params
                 .iter()
                 .enumerate()
                 // .inspect(|v| {
@@ -4,6 +4,7 @@ pub trait Printer {
     fn stderr(&mut self, msg: &str) -> Result<(), AnyError>;
 }
 
+#[derive(Clone, Copy)]
 pub struct SimplePrinter;
 
 impl Printer for SimplePrinter {
@@ -0,0 +1,105 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use config::{Resources, TaskInfo};
+use runner::{ExecutionError, Executor, SimplePrinter};
+use serde_json::json;
+use source_cache::SourceCache;
+
+/// Every job takes this long
+const JOB: Duration = Duration::from_millis(300);
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gilbert-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("job.js"),
+        format!(
+            "await new Promise((resolve) => setTimeout(resolve, {}));\n",
+            JOB.as_millis()
+        ),
+    )
+    .unwrap();
+    dir
+}
+
+fn executor(dir: &Path, capacity: Option<Resources>) -> Executor {
+    Executor::new(capacity, None, SourceCache::new(dir.join("cache")))
+}
+
+fn task(dir: &Path, policy: serde_json::Value) -> TaskInfo {
+    let mut task = json!({"params": [], "script": dir.join("job.js")});
+    task.as_object_mut()
+        .unwrap()
+        .extend(policy.as_object().unwrap().clone());
+    serde_json::from_value(task).unwrap()
+}
+
+#[tokio::test]
+async fn concurrency_limit() {
+    let dir = temp_dir("concurrency");
+    let executor = executor(&dir, None);
+
+    let rejecting = task(
+        &dir,
+        json!({"concurrency": {"per_node": 1, "on_limit": "reject"}}),
+    );
+    let (a, b) = tokio::join!(
+        executor.execute("rejecting", &rejecting, vec![], SimplePrinter),
+        executor.execute("rejecting", &rejecting, vec![], SimplePrinter),
+    );
+    assert!(a.is_ok() != b.is_ok());
+    assert!(matches!(
+        a.and(b),
+        Err(ExecutionError::ConcurrencyLimit(name)) if name == "rejecting"
+    ));
+
+    let queueing = task(&dir, json!({"concurrency": {"per_node": 1}}));
+    let start = Instant::now();
+    let (a, b) = tokio::join!(
+        executor.execute("queueing", &queueing, vec![], SimplePrinter),
+        executor.execute("queueing", &queueing, vec![], SimplePrinter),
+    );
+    a.unwrap();
+    b.unwrap();
+    assert!(start.elapsed() >= 2 * JOB);
+    assert!(executor.running().values().all(|&running| running == 0));
+}
+
+#[tokio::test]
+async fn resource_reservation() {
+    let dir = temp_dir("resources");
+    let capacity = serde_json::from_value(json!({"cpu": 2, "memory": 1024})).unwrap();
+    let executor = executor(&dir, Some(capacity));
+
+    let too_big = task(&dir, json!({"resources": {"cpu": 4}}));
+    assert!(matches!(
+        executor.execute("too_big", &too_big, vec![], SimplePrinter).await,
+        Err(ExecutionError::InsufficientResources(name)) if name == "too_big"
+    ));
+
+    // Only one of them fits at a time, the other one waits for it
+    let half = task(&dir, json!({"resources": {"cpu": 1, "memory": 768}}));
+    let start = Instant::now();
+    let (a, b) = tokio::join!(
+        executor.execute("a", &half, vec![], SimplePrinter),
+        executor.execute("b", &half, vec![], SimplePrinter),
+    );
+    a.unwrap();
+    b.unwrap();
+    assert!(start.elapsed() >= 2 * JOB);
+
+    // Both fit together
+    let small = task(&dir, json!({"resources": {"cpu": 1, "memory": 512}}));
+    let start = Instant::now();
+    let (a, b) = tokio::join!(
+        executor.execute("a", &small, vec![], SimplePrinter),
+        executor.execute("b", &small, vec![], SimplePrinter),
+    );
+    a.unwrap();
+    b.unwrap();
+    assert!(start.elapsed() < 2 * JOB);
+}
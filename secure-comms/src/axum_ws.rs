@@ -1,10 +1,23 @@
 use std::task::Poll;
 
 use axum::extract::ws::{Message, WebSocket};
-use futures_util::{Sink, Stream};
+use futures_util::Sink;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::WebSocketByteStream;
+use crate::{
+    byte_stream::{poll_read, Incoming, WsMessage},
+    WebSocketByteStream,
+};
+
+impl WsMessage for Message {
+    fn incoming(self) -> Incoming {
+        match self {
+            Message::Binary(data) => Incoming::Data(data),
+            Message::Close(_) => Incoming::Closed,
+            Message::Text(_) | Message::Ping(_) | Message::Pong(_) => Incoming::Skip,
+        }
+    }
+}
 
 impl AsyncRead for WebSocketByteStream<WebSocket> {
     fn poll_read(
@@ -13,33 +26,13 @@ impl AsyncRead for WebSocketByteStream<WebSocket> {
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         let projection = self.project();
-        let res = projection.socket.poll_next(cx);
-        match res {
-            Poll::Ready(data) => match data {
-                None | Some(Ok(Message::Close(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionReset,
-                    "Web socket closed",
-                ))),
-                Some(Err(e)) => Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, e))),
-                Some(Ok(Message::Binary(b))) => {
-                    buf.put_slice(&b);
-                    Poll::Ready(Ok(()))
-                }
-                Some(Ok(Message::Text(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received text kind data",
-                ))),
-                Some(Ok(Message::Ping(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received ping",
-                ))),
-                Some(Ok(Message::Pong(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received pong",
-                ))),
-            },
-            Poll::Pending => Poll::Pending,
-        }
+        poll_read(
+            projection.socket,
+            projection.pending,
+            projection.read,
+            cx,
+            buf,
+        )
     }
 }
 
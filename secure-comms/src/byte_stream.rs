@@ -0,0 +1,63 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::io::ReadBuf;
+
+/// What a received WebSocket message means for the byte stream
+pub(crate) enum Incoming {
+    Data(Vec<u8>),
+    Closed,
+    /// Pings are answered by the WebSocket implementations themselves,
+    /// so they, pongs and text are just skipped
+    Skip,
+}
+
+pub(crate) trait WsMessage {
+    fn incoming(self) -> Incoming;
+}
+
+/// Fills `buf` from the pending bytes, reading the next data message once they run out
+pub(crate) fn poll_read<S, M, E>(
+    mut socket: Pin<&mut S>,
+    pending: &mut Vec<u8>,
+    read: &mut usize,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+) -> Poll<std::io::Result<()>>
+where
+    S: Stream<Item = Result<M, E>>,
+    M: WsMessage,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    loop {
+        if *read < pending.len() {
+            let n = buf.remaining().min(pending.len() - *read);
+            buf.put_slice(&pending[*read..*read + n]);
+            *read += n;
+            if *read == pending.len() {
+                pending.clear();
+                *read = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        match ready!(socket.as_mut().poll_next(cx)) {
+            None => return Poll::Ready(Err(closed())),
+            Some(Err(e)) => return Poll::Ready(Err(std::io::Error::other(e))),
+            Some(Ok(message)) => match message.incoming() {
+                Incoming::Data(data) => {
+                    *pending = data;
+                    *read = 0;
+                }
+                Incoming::Closed => return Poll::Ready(Err(closed())),
+                Incoming::Skip => {}
+            },
+        }
+    }
+}
+
+fn closed() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Web socket closed")
+}
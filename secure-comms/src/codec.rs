@@ -0,0 +1,100 @@
+use std::fmt::Display;
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// Formats the messages can be serialized in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Bincode,
+    MessagePack,
+    Cbor,
+    Json,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid {codec} message: {source}")]
+pub struct CodecError {
+    codec: Codec,
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl Codec {
+    const fn tag(self) -> u8 {
+        match self {
+            Self::Bincode => 0,
+            Self::MessagePack => 1,
+            Self::Cbor => 2,
+            Self::Json => 3,
+        }
+    }
+
+    fn error<E: std::error::Error + Send + Sync + 'static>(self) -> impl FnOnce(E) -> CodecError {
+        move |e| CodecError {
+            codec: self,
+            source: Box::new(e),
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Self::Bincode => bincode::serialize(msg).map_err(self.error()),
+            // Structs as maps, so the peers can add fields
+            Self::MessagePack => rmp_serde::to_vec_named(msg).map_err(self.error()),
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(msg, &mut buf).map_err(self.error())?;
+                Ok(buf)
+            }
+            Self::Json => serde_json::to_vec(msg).map_err(self.error()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Self::Bincode => bincode::deserialize(data).map_err(self.error()),
+            Self::MessagePack => rmp_serde::from_slice(data).map_err(self.error()),
+            Self::Cbor => ciborium::from_reader(data).map_err(self.error()),
+            Self::Json => serde_json::from_slice(data).map_err(self.error()),
+        }
+    }
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Bincode => "bincode",
+            Self::MessagePack => "MessagePack",
+            Self::Cbor => "CBOR",
+            Self::Json => "JSON",
+        })
+    }
+}
+
+/// Codecs used on a connection, which can differ by direction
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Codecs {
+    pub(crate) send: Codec,
+    pub(crate) receive: Codec,
+}
+
+/// Frame telling the peer the codecs this side takes, by preference
+pub(crate) fn offer(codecs: &[Codec]) -> Bytes {
+    codecs.iter().map(|c| c.tag()).collect()
+}
+
+/// Each side sends with its most preferred codec the other offers
+pub(crate) fn pick(codecs: &[Codec], peer: &[u8]) -> std::io::Result<Codecs> {
+    let send = codecs.iter().copied().find(|c| peer.contains(&c.tag()));
+    let receive = peer
+        .iter()
+        .find_map(|tag| codecs.iter().copied().find(|c| c.tag() == *tag));
+    match (send, receive) {
+        (Some(send), Some(receive)) => Ok(Codecs { send, receive }),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the peer offers no codec in common",
+        )),
+    }
+}
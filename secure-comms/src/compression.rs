@@ -0,0 +1,139 @@
+use std::io::{Read, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Compression algorithms a frame can be sent with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Deflate,
+    Zstd,
+}
+
+/// Tags at the start of every frame, telling how the rest of it is compressed
+const RAW: u8 = 0;
+const DEFLATE: u8 = 1;
+const ZSTD: u8 = 2;
+
+impl Compression {
+    const fn tag(self) -> u8 {
+        match self {
+            Self::Deflate => DEFLATE,
+            Self::Zstd => ZSTD,
+        }
+    }
+
+    const fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            DEFLATE => Some(Self::Deflate),
+            ZSTD => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// What a side of the connection offers, the algorithm used is its most preferred one the peer offers too
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Algorithms by preference, nothing is compressed if empty
+    pub algorithms: Vec<Compression>,
+    /// Frames smaller than this, in bytes, are sent uncompressed
+    pub threshold: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: Vec::new(),
+            threshold: 1024,
+        }
+    }
+}
+
+/// Compression used to send frames on a connection
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Negotiated {
+    algorithm: Option<Compression>,
+    threshold: usize,
+}
+
+/// Frame telling the peer the offered algorithms
+pub(crate) fn offer(config: &CompressionConfig) -> Bytes {
+    config.algorithms.iter().map(|a| a.tag()).collect()
+}
+
+/// Picks the most preferred algorithm the peer offers too.
+/// Tags the peer knows and this side doesn't are just ignored
+pub(crate) fn pick(config: &CompressionConfig, peer: &[u8]) -> Negotiated {
+    let algorithm = config
+        .algorithms
+        .iter()
+        .copied()
+        .find(|a| peer.contains(&a.tag()));
+    Negotiated {
+        algorithm,
+        threshold: config.threshold,
+    }
+}
+
+impl Negotiated {
+    pub(crate) fn encode(self, data: &[u8]) -> std::io::Result<Bytes> {
+        let compressed = match self.algorithm {
+            Some(algorithm) if data.len() >= self.threshold => {
+                Some((algorithm.tag(), compress(algorithm, data)?))
+            }
+            _ => None,
+        };
+        let mut frame = BytesMut::with_capacity(data.len() + 1);
+        match compressed {
+            // Small or random data can grow when compressed
+            Some((tag, compressed)) if compressed.len() < data.len() => {
+                frame.put_u8(tag);
+                frame.put_slice(&compressed);
+            }
+            _ => {
+                frame.put_u8(RAW);
+                frame.put_slice(data);
+            }
+        }
+        Ok(frame.freeze())
+    }
+}
+
+/// Undoes [`Negotiated::encode`]. Every known algorithm is accepted,
+/// as the tag tells which one the frame uses. Frames growing past `limit` are refused
+pub(crate) fn decode(frame: &[u8], limit: usize) -> std::io::Result<Vec<u8>> {
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let (&tag, data) = frame.split_first().ok_or_else(|| invalid("empty frame"))?;
+    if tag == RAW {
+        return Ok(data.to_vec());
+    }
+    match Compression::from_tag(tag) {
+        Some(Compression::Deflate) => read_limited(flate2::read::DeflateDecoder::new(data), limit),
+        Some(Compression::Zstd) => read_limited(zstd::stream::read::Decoder::new(data)?, limit),
+        None => Err(invalid("unknown frame compression")),
+    }
+}
+
+fn read_limited(reader: impl Read, limit: usize) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "frame too large once decompressed",
+        ));
+    }
+    Ok(decoded)
+}
+
+fn compress(algorithm: Compression, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match algorithm {
+        Compression::Deflate => {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compression::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+    }
+}
@@ -1,6 +1,7 @@
-use std::{borrow::Cow, marker::PhantomData, sync::Arc};
+use std::{future::Future, marker::PhantomData, sync::Arc, time::Duration};
 
-use futures_util::{Sink, Stream};
+use bytes::BytesMut;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use pin_project::pin_project;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -11,22 +12,85 @@ use tokio_rustls::{
     server, TlsAcceptor, TlsConnector,
 };
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use x509_parser::extensions::GeneralName;
 
+use codec::Codecs;
+pub use codec::{Codec, CodecError};
+use compression::Negotiated;
+pub use compression::{Compression, CompressionConfig};
+pub use mux::{Channel, ChannelReceiver, ChannelSender, Frame, Multiplexer, WINDOW};
+pub use transport::{accept_quic, BoxedIo, Quic, SecureIo, Tcp, Transport, WebSocket};
+
+/// Byte stream over the binary messages of a WebSocket.
+/// Messages larger than the read buffer are handed out over several reads,
+/// and anything that isn't binary data is skipped
 #[pin_project]
 #[derive(Debug)]
 pub struct WebSocketByteStream<W> {
     #[pin]
     socket: W,
+    /// Part of the last message that didn't fit in the read buffer
+    pending: Vec<u8>,
+    read: usize,
+}
+
+impl<W> WebSocketByteStream<W> {
+    pub const fn new(socket: W) -> Self {
+        Self {
+            socket,
+            pending: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+/// Frames of messages up to the size, with the tag of their compression
+fn codec(max_message_size: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_message_size + 1)
+        .new_codec()
+}
+
+/// Fails with [`std::io::ErrorKind::TimedOut`] unless the handshake is done in time
+pub async fn handshake_deadline<T, E, F>(timeout: Duration, handshake: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<std::io::Error>,
+{
+    tokio::time::timeout(timeout, handshake)
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the handshake timed out").into())
+        })
+}
+
+/// What a side of the connection offers the peer, to agree on how the messages are sent
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    pub compression: CompressionConfig,
+    /// Codecs by preference, the messages are sent in the first one the peer offers too
+    pub codecs: Vec<Codec>,
+    /// Largest message either side sends or takes, in bytes
+    pub max_message_size: usize,
+    /// Time the peer has for the TLS handshake and the negotiation, when accepting
+    pub handshake_timeout: Duration,
 }
 
-#[inline]
-fn codec() -> LengthDelimitedCodec {
-    LengthDelimitedCodec::default()
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            compression: CompressionConfig::default(),
+            codecs: vec![Codec::MessagePack, Codec::Bincode],
+            max_message_size: 16 * 1024 * 1024,
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Acceptor {
     tls: TlsAcceptor,
+    config: StreamConfig,
 }
 
 impl<T> From<T> for Acceptor
@@ -34,11 +98,20 @@ where
     T: Into<TlsAcceptor>,
 {
     fn from(value: T) -> Self {
-        Self { tls: value.into() }
+        Self {
+            tls: value.into(),
+            config: StreamConfig::default(),
+        }
     }
 }
 
 impl Acceptor {
+    #[must_use]
+    pub fn with_config(mut self, config: StreamConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     pub async fn accept<W: Send, I, O>(
         &self,
         ws: W,
@@ -46,12 +119,11 @@ impl Acceptor {
     where
         WebSocketByteStream<W>: AsyncRead + AsyncWrite + Unpin,
     {
-        let stream = self.tls.accept(WebSocketByteStream { socket: ws }).await?;
-        let framed = Framed::new(stream, codec());
-        Ok(DataStream {
-            inner: framed,
-            msg: PhantomData,
+        handshake_deadline(self.config.handshake_timeout, async {
+            let stream = self.tls.accept(WebSocketByteStream::new(ws)).await?;
+            DataStream::negotiate(stream, &self.config).await
         })
+        .await
     }
 
     pub async fn accept_with<W: Send, I, O, F>(
@@ -63,71 +135,92 @@ impl Acceptor {
         WebSocketByteStream<W>: AsyncRead + AsyncWrite + Unpin,
         F: FnOnce(&mut ServerConnection) + Send,
     {
-        let stream = self
-            .tls
-            .accept_with(WebSocketByteStream { socket: ws }, f)
-            .await?;
-        let framed = Framed::new(stream, codec());
-        Ok(DataStream {
-            inner: framed,
-            msg: PhantomData,
+        handshake_deadline(self.config.handshake_timeout, async {
+            let stream = self
+                .tls
+                .accept_with(WebSocketByteStream::new(ws), f)
+                .await?;
+            DataStream::negotiate(stream, &self.config).await
         })
+        .await
     }
 
-    pub async fn accept_with_server_name<W: Send, I, O>(
+    /// Does the TLS handshake over `io`, then sets up the messages as [`Acceptor::established`] does
+    pub async fn accept_tls<S, I, O>(
         &self,
-        ws: W,
-    ) -> Result<
-        (
-            DataStream<server::TlsStream<WebSocketByteStream<W>>, I, O>,
-            Option<String>,
-        ),
-        AcceptError,
-    >
+        io: S,
+    ) -> Result<(DataStream<BoxedIo, I, O>, Peer), AcceptError>
     where
-        WebSocketByteStream<W>: AsyncRead + AsyncWrite + Unpin,
-        I: Send,
-        O: Send,
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
-        let stream = self.tls.accept(WebSocketByteStream { socket: ws }).await?;
-        let mut i = 0;
-        while i < 20 && stream.get_ref().1.peer_certificates().is_none() {
-            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
-            i += 1;
-        }
-        let base_cert = &stream
-            .get_ref()
-            .1
-            .peer_certificates()
-            .and_then(|certs| certs.get(0))
-            .ok_or(AcceptError::NoPeerCerts)?;
-        let (_, parsed) =
-            x509_parser::parse_x509_certificate(&base_cert.0).map_err(|e| match e {
-                nom::Err::Incomplete(_) => InvalidCertError::DataNeeded,
-                nom::Err::Error(e) | nom::Err::Failure(e) => InvalidCertError::X509(e),
-            })?;
-        let names = parsed
-            .subject_alternative_name()
-            .unwrap()
-            .map(|san| Cow::Borrowed(san.value.general_names.as_slice()))
-            // .and_then::<GeneralName<'_>, _>(|san| san.value.general_names.get(0).cloned())
-            .unwrap_or_else(|| {
-                Cow::Owned(vec![x509_parser::prelude::GeneralName::DirectoryName(
-                    parsed.subject().clone(),
-                )])
-            });
-        let name = names.get(0).map(ToString::to_string);
-        let framed = Framed::new(stream, codec());
-        Ok((
-            DataStream {
-                inner: framed,
-                msg: PhantomData,
-            },
-            name,
-        ))
+        handshake_deadline(self.config.handshake_timeout, async {
+            let stream = self.tls.accept(io).await?;
+            self.established(Box::new(stream)).await
+        })
+        .await
+    }
+
+    /// Sets up the messages over a connection that did its TLS handshake already, returning
+    /// who the peer is. Checking it against the known peers is up to the caller
+    pub async fn established<I, O>(
+        &self,
+        io: BoxedIo,
+    ) -> Result<(DataStream<BoxedIo, I, O>, Peer), AcceptError> {
+        let certificate = io
+            .peer_certificate()
+            .ok_or(AcceptError::NoPeerCerts)?
+            .to_vec();
+        let names = peer_names(&certificate)?;
+        let stream = handshake_deadline(
+            self.config.handshake_timeout,
+            DataStream::negotiate(io, &self.config),
+        )
+        .await?;
+        Ok((stream, Peer { names, certificate }))
     }
 }
 
+/// Peer of an accepted connection
+#[derive(Debug, Clone)]
+pub struct Peer {
+    /// DNS names in its certificate, or its common name if it has none
+    pub names: Vec<String>,
+    /// Its certificate, DER encoded
+    pub certificate: Vec<u8>,
+}
+
+/// DNS names of a DER encoded certificate, or its common name if it has none
+pub fn peer_names(der: &[u8]) -> Result<Vec<String>, AcceptError> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(der).map_err(|e| match e {
+        nom::Err::Incomplete(_) => InvalidCertError::DataNeeded,
+        nom::Err::Error(e) | nom::Err::Failure(e) => InvalidCertError::X509(e),
+    })?;
+    let san = parsed
+        .subject_alternative_name()
+        .map_err(|_| AcceptError::SubjectAltNameExtError)?;
+    let names = san
+        .map(|san| {
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some((*name).to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if !names.is_empty() {
+        return Ok(names);
+    }
+    Ok(parsed
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(str::to_string)
+        .collect())
+}
+
 #[derive(Debug, Error)]
 pub enum AcceptError {
     #[error(transparent)]
@@ -156,33 +249,76 @@ pub async fn connector<W: Send, I, O>(
     ws: W,
     domain: ServerName,
     config: Arc<ClientConfig>,
+    stream_config: &StreamConfig,
 ) -> std::io::Result<DataStream<client::TlsStream<WebSocketByteStream<W>>, I, O>>
 where
     WebSocketByteStream<W>: AsyncRead + AsyncWrite + Unpin,
 {
     let stream = TlsConnector::from(config)
-        .connect(domain, WebSocketByteStream { socket: ws })
+        .connect(domain, WebSocketByteStream::new(ws))
         .await?;
-    let framed = Framed::new(stream, codec());
-    Ok(DataStream {
-        inner: framed,
-        msg: PhantomData,
-    })
+    DataStream::negotiate(stream, stream_config).await
 }
 
 #[pin_project]
 pub struct DataStream<T, I, O = I> {
     #[pin]
     inner: Framed<T, LengthDelimitedCodec>,
+    compression: Negotiated,
+    codecs: Codecs,
+    max_message_size: usize,
     msg: PhantomData<(I, O)>,
 }
 
+impl<T, I, O> DataStream<T, I, O>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Sets up the messages over an established connection, agreeing on the compression
+    /// and codecs with the peer. Both sides send their offers first, so neither waits on the other
+    pub async fn negotiate(io: T, config: &StreamConfig) -> std::io::Result<Self> {
+        let mut inner = Framed::new(io, codec(config.max_message_size));
+        inner.feed(compression::offer(&config.compression)).await?;
+        inner.send(codec::offer(&config.codecs)).await?;
+        let compression = compression::pick(&config.compression, &offered(&mut inner).await?);
+        let codecs = codec::pick(&config.codecs, &offered(&mut inner).await?)?;
+        Ok(Self {
+            inner,
+            compression,
+            codecs,
+            max_message_size: config.max_message_size,
+            msg: PhantomData,
+        })
+    }
+}
+
+/// Next offer of the peer while negotiating
+async fn offered<T>(framed: &mut Framed<T, LengthDelimitedCodec>) -> std::io::Result<BytesMut>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    framed.next().await.unwrap_or_else(|| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "connection closed while negotiating",
+        ))
+    })
+}
+
 #[derive(Debug, Error)]
 pub enum DataStreamError {
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
-    Bincode(#[from] bincode::Error),
+    Codec(#[from] CodecError),
+    #[error("The connection is closed")]
+    Closed,
+    #[error("Message of {0} bytes is too large")]
+    TooLarge(usize),
+    #[error("The peer has no room for more messages on the channel")]
+    Full,
+    #[error("Protocol violation: {0}")]
+    Protocol(&'static str),
 }
 
 impl<T, I, O> Stream for DataStream<T, I, O>
@@ -196,10 +332,13 @@ where
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        self.project().inner.poll_next(cx).map(|res| {
+        let this = self.project();
+        let codec = this.codecs.receive;
+        let limit = *this.max_message_size;
+        this.inner.poll_next(cx).map(|res| {
             res.map(|res| {
-                res.map_err(Into::into)
-                    .and_then(|bytes| bincode::deserialize(&bytes).map_err(Into::into))
+                let bytes = compression::decode(&res?, limit)?;
+                codec.decode(&bytes).map_err(Into::into)
             })
         })
     }
@@ -220,9 +359,13 @@ where
     }
 
     fn start_send(self: std::pin::Pin<&mut Self>, item: O) -> Result<(), Self::Error> {
-        let mut buf = Vec::with_capacity(bincode::serialized_size(&item)? as usize);
-        bincode::serialize_into(&mut buf, &item)?;
-        self.project().inner.start_send(buf.into())?;
+        let this = self.project();
+        let buf = this.codecs.send.encode(&item)?;
+        if buf.len() > *this.max_message_size {
+            return Err(DataStreamError::TooLarge(buf.len()));
+        }
+        let frame = this.compression.encode(&buf)?;
+        this.inner.start_send(frame)?;
         Ok(())
     }
 
@@ -241,5 +384,10 @@ where
     }
 }
 
+mod byte_stream;
+mod codec;
+mod compression;
+mod mux;
+mod transport;
 pub mod axum_ws;
 pub mod tungstenite;
@@ -0,0 +1,395 @@
+use std::{
+    collections::VecDeque,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc, Semaphore, TryAcquireError},
+};
+
+use crate::{
+    codec::{Codec, Codecs},
+    DataStream, DataStreamError,
+};
+
+/// Logical channels of a connection, each with its own messages and flow control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Handshakes, config updates and pings
+    Control,
+    /// Placing tasks and reporting on them
+    Task,
+    /// Output of running tasks
+    Logs,
+    /// Artifacts and other large transfers
+    Bulk,
+}
+
+const CHANNELS: [Channel; 4] = [
+    Channel::Control,
+    Channel::Task,
+    Channel::Logs,
+    Channel::Bulk,
+];
+
+/// Chunks a channel can have sent that the peer didn't take yet, so about 1 MiB.
+/// A message counts as the chunks it fills, and a message larger than the window as all of it
+pub const WINDOW: usize = 64;
+/// Largest part of a message in a frame, so a large message doesn't hold up the other channels.
+/// With JSON the part is escaped as a string, which at most doubles it
+const CHUNK: usize = 16 * 1024;
+
+/// Credit a message of `len` bytes takes from the window of its channel
+fn cost(len: usize) -> usize {
+    len.div_ceil(CHUNK).clamp(1, WINDOW)
+}
+
+/// What a multiplexed connection carries
+#[derive(Serialize, Deserialize)]
+pub struct Frame {
+    channel: u8,
+    kind: FrameKind,
+}
+
+#[derive(Serialize, Deserialize)]
+enum FrameKind {
+    /// Part of a message, which the last part completes
+    Data {
+        last: bool,
+        #[serde(with = "payload")]
+        data: Vec<u8>,
+    },
+    /// The peer took messages worth this many chunks, so as many more can be sent
+    Credit(u32),
+}
+
+/// The part of a message, as bytes, or as a string with a readable codec.
+/// Messages in a readable codec are text, and they're only split between characters
+mod payload {
+    use super::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::ser::Error as _;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            std::str::from_utf8(data)
+                .map_err(S::Error::custom)?
+                .serialize(serializer)
+        } else {
+            serde_bytes::serialize(data, serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer).map(String::into_bytes)
+        } else {
+            serde_bytes::deserialize(deserializer)
+        }
+    }
+}
+
+/// A whole message, or the error that ended the connection
+type Received = Result<Vec<u8>, DataStreamError>;
+
+enum Outgoing {
+    Message(Channel, Vec<u8>),
+    Credit(Channel, u32),
+}
+
+/// Shared by the reading task and the ends of a channel
+#[derive(Clone)]
+struct ChannelState {
+    /// Chunks that can be sent before the peer takes some
+    credit: Arc<Semaphore>,
+    /// Chunks of the messages received and not taken yet
+    queued: Arc<AtomicUsize>,
+}
+
+struct ChannelEnds {
+    state: ChannelState,
+    inbound: mpsc::UnboundedReceiver<Received>,
+}
+
+/// Channels over one connection. Every channel can be taken once, with the message types
+/// the peer uses on it. Messages of a channel nobody took are kept until its window is full,
+/// or dropped with the multiplexer
+pub struct Multiplexer {
+    codecs: Codecs,
+    max_message_size: usize,
+    writer: mpsc::UnboundedSender<Outgoing>,
+    channels: Mutex<[Option<ChannelEnds>; 4]>,
+}
+
+impl Multiplexer {
+    /// Starts the tasks reading and writing the connection, which is closed once
+    /// the multiplexer and the ends of its channels are dropped
+    pub fn new<T>(stream: DataStream<T, Frame>) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let codecs = stream.codecs;
+        let max_message_size = stream.max_message_size;
+        let (sink, stream) = stream.split();
+        let (writer, outgoing) = mpsc::unbounded_channel();
+        let mut inbound = Vec::new();
+        let channels = CHANNELS.map(|_| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let state = ChannelState {
+                credit: Arc::new(Semaphore::new(WINDOW)),
+                queued: Arc::default(),
+            };
+            inbound.push((tx, state.clone()));
+            Some(ChannelEnds { state, inbound: rx })
+        });
+        let credits = inbound
+            .iter()
+            .map(|(_, state)| state.credit.clone())
+            .collect();
+        tokio::spawn(write(sink, outgoing, credits, codecs.send));
+        tokio::spawn(read(stream, inbound, max_message_size));
+        Self {
+            codecs,
+            max_message_size,
+            writer,
+            channels: Mutex::new(channels),
+        }
+    }
+
+    /// Ends of the channel, or `None` if it was taken already
+    pub fn channel<I, O>(
+        &self,
+        channel: Channel,
+    ) -> Option<(ChannelSender<O>, ChannelReceiver<I>)> {
+        let ends = self.channels.lock().unwrap()[channel as usize].take()?;
+        let sender = ChannelSender {
+            channel,
+            codec: self.codecs.send,
+            max_message_size: self.max_message_size,
+            credit: ends.state.credit,
+            writer: self.writer.clone(),
+            msg: PhantomData,
+        };
+        let receiver = ChannelReceiver {
+            channel,
+            codec: self.codecs.receive,
+            queued: ends.state.queued,
+            inbound: ends.inbound,
+            writer: self.writer.clone(),
+            msg: PhantomData,
+        };
+        Some((sender, receiver))
+    }
+}
+
+/// Sends the messages, a part of a message of every channel in turn
+async fn write<S>(
+    mut sink: S,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    credits: Vec<Arc<Semaphore>>,
+    codec: Codec,
+) where
+    S: Sink<Frame, Error = DataStreamError> + Unpin,
+{
+    let mut pending: [VecDeque<Vec<u8>>; 4] = Default::default();
+    // How much of the first pending message of each channel was sent
+    let mut sent = [0; 4];
+    let _: Result<(), DataStreamError> = async {
+        loop {
+            let mut next = if pending.iter().all(VecDeque::is_empty) {
+                match outgoing.recv().await {
+                    Some(out) => Some(out),
+                    None => return sink.close().await,
+                }
+            } else {
+                None
+            };
+            while let Some(out) = next.take().or_else(|| outgoing.try_recv().ok()) {
+                match out {
+                    Outgoing::Message(channel, data) => pending[channel as usize].push_back(data),
+                    // Credits go out right away, the peer may be waiting on them
+                    Outgoing::Credit(channel, taken) => {
+                        sink.feed(Frame {
+                            channel: channel as u8,
+                            kind: FrameKind::Credit(taken),
+                        })
+                        .await?;
+                    }
+                }
+            }
+            for (channel, queue) in pending.iter_mut().enumerate() {
+                let Some(message) = queue.front() else {
+                    continue;
+                };
+                let mut end = message.len().min(sent[channel] + CHUNK);
+                if codec == Codec::Json {
+                    // Back to the start of the character, continuation bytes are 0b10xxxxxx
+                    while end < message.len() && message[end] & 0xc0 == 0x80 {
+                        end -= 1;
+                    }
+                }
+                let last = end == message.len();
+                let data = message[sent[channel]..end].to_vec();
+                if last {
+                    queue.pop_front();
+                    sent[channel] = 0;
+                } else {
+                    sent[channel] = end;
+                }
+                sink.feed(Frame {
+                    channel: channel as u8,
+                    kind: FrameKind::Data { last, data },
+                })
+                .await?;
+            }
+            sink.flush().await?;
+        }
+    }
+    .await;
+    // Wakes the senders waiting for credit
+    for credit in credits {
+        credit.close();
+    }
+}
+
+/// Puts the messages together and hands them to their channels.
+/// An error of the connection is given to the control channel
+async fn read<S>(
+    mut stream: S,
+    channels: Vec<(mpsc::UnboundedSender<Received>, ChannelState)>,
+    max_message_size: usize,
+) where
+    S: Stream<Item = Result<Frame, DataStreamError>> + Unpin,
+{
+    let mut partial: [Vec<u8>; 4] = Default::default();
+    let res = loop {
+        let frame = match stream.next().await {
+            None => break Ok(()),
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => break Err(e),
+        };
+        let index = usize::from(frame.channel);
+        let Some((inbound, state)) = channels.get(index) else {
+            break Err(DataStreamError::Protocol("unknown channel"));
+        };
+        match frame.kind {
+            FrameKind::Data { last, data } => {
+                if partial[index].len() + data.len() > max_message_size {
+                    break Err(DataStreamError::Protocol("message too large"));
+                }
+                partial[index].extend_from_slice(&data);
+                if last {
+                    let cost = cost(partial[index].len());
+                    if state.queued.fetch_add(cost, Ordering::AcqRel) + cost > WINDOW {
+                        break Err(DataStreamError::Protocol("the peer overran a channel"));
+                    }
+                    let _ = inbound.send(Ok(std::mem::take(&mut partial[index])));
+                }
+            }
+            FrameKind::Credit(taken) => {
+                // More credit than the window would only come from a misbehaving peer
+                let room = WINDOW.saturating_sub(state.credit.available_permits());
+                state.credit.add_permits((taken as usize).min(room));
+            }
+        }
+    };
+    if let Err(e) = res {
+        let _ = channels[Channel::Control as usize].0.send(Err(e));
+    }
+    for (_, state) in &channels {
+        state.credit.close();
+    }
+}
+
+/// Sending end of a channel, which can be cloned to send from several places
+pub struct ChannelSender<O> {
+    channel: Channel,
+    codec: Codec,
+    max_message_size: usize,
+    credit: Arc<Semaphore>,
+    writer: mpsc::UnboundedSender<Outgoing>,
+    msg: PhantomData<fn(O)>,
+}
+
+impl<O> Clone for ChannelSender<O> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel,
+            codec: self.codec,
+            max_message_size: self.max_message_size,
+            credit: self.credit.clone(),
+            writer: self.writer.clone(),
+            msg: PhantomData,
+        }
+    }
+}
+
+impl<O: Serialize> ChannelSender<O> {
+    /// Sends the message once the peer has room for it on the channel
+    pub async fn send(&self, msg: O) -> Result<(), DataStreamError> {
+        let data = self.codec.encode(&msg)?;
+        if data.len() > self.max_message_size {
+            return Err(DataStreamError::TooLarge(data.len()));
+        }
+        self.credit
+            .acquire_many(cost(data.len()) as u32)
+            .await
+            .map_err(|_| DataStreamError::Closed)?
+            .forget();
+        self.writer
+            .send(Outgoing::Message(self.channel, data))
+            .map_err(|_| DataStreamError::Closed)
+    }
+
+    /// Sends the message if the peer has room for it on the channel right away,
+    /// for replies that mustn't hold up the receiving end
+    pub fn try_send(&self, msg: O) -> Result<(), DataStreamError> {
+        let data = self.codec.encode(&msg)?;
+        if data.len() > self.max_message_size {
+            return Err(DataStreamError::TooLarge(data.len()));
+        }
+        match self.credit.try_acquire_many(cost(data.len()) as u32) {
+            Ok(permit) => permit.forget(),
+            Err(TryAcquireError::NoPermits) => return Err(DataStreamError::Full),
+            Err(TryAcquireError::Closed) => return Err(DataStreamError::Closed),
+        }
+        self.writer
+            .send(Outgoing::Message(self.channel, data))
+            .map_err(|_| DataStreamError::Closed)
+    }
+}
+
+/// Receiving end of a channel, taking a message gives the peer room for as many chunks as it took
+pub struct ChannelReceiver<I> {
+    channel: Channel,
+    codec: Codec,
+    queued: Arc<AtomicUsize>,
+    inbound: mpsc::UnboundedReceiver<Received>,
+    writer: mpsc::UnboundedSender<Outgoing>,
+    msg: PhantomData<fn() -> I>,
+}
+
+impl<I: DeserializeOwned> Stream for ChannelReceiver<I> {
+    type Item = Result<I, DataStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.inbound.poll_recv(cx).map(|res| {
+            res.map(|res| {
+                let data = res?;
+                let cost = cost(data.len());
+                this.queued.fetch_sub(cost, Ordering::AcqRel);
+                let _ = this.writer.send(Outgoing::Credit(this.channel, cost as u32));
+                this.codec.decode(&data).map_err(Into::into)
+            })
+        })
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::{
+    rustls::{ClientConfig, ServerName},
+    TlsConnector,
+};
+use url::Url;
+
+use crate::WebSocketByteStream;
+
+/// Byte stream to a peer, encrypted and authenticated with its certificate
+pub trait SecureIo: AsyncRead + AsyncWrite + Unpin + Send {
+    /// Certificate the peer presented, DER encoded
+    fn peer_certificate(&self) -> Option<&[u8]>;
+}
+
+pub type BoxedIo = Box<dyn SecureIo>;
+
+impl<T> SecureIo for tokio_rustls::server::TlsStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn peer_certificate(&self) -> Option<&[u8]> {
+        self.get_ref()
+            .1
+            .peer_certificates()
+            .and_then(<[_]>::first)
+            .map(|cert| cert.0.as_slice())
+    }
+}
+
+impl<T> SecureIo for tokio_rustls::client::TlsStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn peer_certificate(&self) -> Option<&[u8]> {
+        self.get_ref()
+            .1
+            .peer_certificates()
+            .and_then(<[_]>::first)
+            .map(|cert| cert.0.as_slice())
+    }
+}
+
+/// A way of dialing the other nodes
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Connects to the node at `address` and does the TLS handshake, checking it is `name`
+    async fn connect(
+        &self,
+        address: &Url,
+        name: ServerName,
+        tls: Arc<ClientConfig>,
+    ) -> io::Result<BoxedIo>;
+}
+
+/// Host and port of an address, which needs an explicit port unless the scheme has a default one
+async fn resolve(address: &Url) -> io::Result<SocketAddr> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let host = address
+        .host_str()
+        .ok_or_else(|| invalid(format!("{address} has no host")))?;
+    let port = address
+        .port_or_known_default()
+        .ok_or_else(|| invalid(format!("{address} has no port")))?;
+    tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await?
+        .next()
+        .ok_or_else(|| invalid(format!("{host} doesn't resolve to any address")))
+}
+
+/// TLS nested in the binary messages of a WebSocket, for `http` and `https` addresses
+pub struct WebSocket {
+    /// Path the peers accept the WebSocket at
+    pub path: String,
+}
+
+#[async_trait]
+impl Transport for WebSocket {
+    async fn connect(
+        &self,
+        address: &Url,
+        name: ServerName,
+        tls: Arc<ClientConfig>,
+    ) -> io::Result<BoxedIo> {
+        let mut url = address.join(&self.path).map_err(io::Error::other)?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|()| io::Error::other(format!("{address} is not an HTTP address")))?;
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(io::Error::other)?;
+        let stream = TlsConnector::from(tls)
+            .connect(name, WebSocketByteStream::new(ws))
+            .await?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// TLS straight over TCP, for `tls` addresses
+pub struct Tcp;
+
+#[async_trait]
+impl Transport for Tcp {
+    async fn connect(
+        &self,
+        address: &Url,
+        name: ServerName,
+        tls: Arc<ClientConfig>,
+    ) -> io::Result<BoxedIo> {
+        let tcp = tokio::net::TcpStream::connect(resolve(address).await?).await?;
+        tcp.set_nodelay(true)?;
+        let stream = TlsConnector::from(tls).connect(name, tcp).await?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// A bidirectional stream of a QUIC connection, for `quic` addresses.
+/// QUIC does its own TLS 1.3 handshake, with the same configs as the other transports
+#[derive(Default)]
+pub struct Quic {
+    /// Bound on the first connection, as that has to happen inside the runtime
+    endpoint: OnceLock<quinn::Endpoint>,
+}
+
+impl Quic {
+    fn endpoint(&self) -> io::Result<&quinn::Endpoint> {
+        if let Some(endpoint) = self.endpoint.get() {
+            return Ok(endpoint);
+        }
+        let endpoint = quinn::Endpoint::client(SocketAddr::from(([0; 16], 0)))
+            .or_else(|_| quinn::Endpoint::client(SocketAddr::from(([0; 4], 0))))?;
+        Ok(self.endpoint.get_or_init(|| endpoint))
+    }
+}
+
+#[async_trait]
+impl Transport for Quic {
+    async fn connect(
+        &self,
+        address: &Url,
+        name: ServerName,
+        tls: Arc<ClientConfig>,
+    ) -> io::Result<BoxedIo> {
+        let ServerName::DnsName(dns_name) = &name else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "QUIC peers are dialed by name",
+            ));
+        };
+        let connection = self
+            .endpoint()?
+            .connect_with(
+                quinn::ClientConfig::new(tls),
+                resolve(address).await?,
+                dns_name.as_ref(),
+            )
+            .map_err(io::Error::other)?
+            .await?;
+        let (send, recv) = connection.open_bi().await?;
+        Ok(Box::new(QuicStream::new(connection, send, recv)))
+    }
+}
+
+/// Waits for the peer to open its stream on a connection accepted by a QUIC endpoint
+pub async fn accept_quic(connecting: quinn::Connecting) -> io::Result<BoxedIo> {
+    let connection = connecting.await?;
+    let (send, recv) = connection.accept_bi().await?;
+    Ok(Box::new(QuicStream::new(connection, send, recv)))
+}
+
+#[pin_project]
+struct QuicStream {
+    #[pin]
+    send: quinn::SendStream,
+    #[pin]
+    recv: quinn::RecvStream,
+    /// Kept for the certificate, and so the connection isn't dropped before the streams
+    _connection: quinn::Connection,
+    certificate: Option<Vec<u8>>,
+}
+
+impl QuicStream {
+    fn new(
+        connection: quinn::Connection,
+        send: quinn::SendStream,
+        recv: quinn::RecvStream,
+    ) -> Self {
+        let certificate = connection
+            .peer_identity()
+            .and_then(|identity| {
+                identity
+                    .downcast::<Vec<tokio_rustls::rustls::Certificate>>()
+                    .ok()
+            })
+            .and_then(|certs| certs.into_iter().next())
+            .map(|cert| cert.0);
+        Self {
+            send,
+            recv,
+            _connection: connection,
+            certificate,
+        }
+    }
+}
+
+impl SecureIo for QuicStream {
+    fn peer_certificate(&self) -> Option<&[u8]> {
+        self.certificate.as_deref()
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().recv.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self.project().send, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self.project().send, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_shutdown(self.project().send, cx)
+    }
+}
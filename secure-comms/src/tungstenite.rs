@@ -1,10 +1,25 @@
 use std::task::Poll;
 
-use futures_util::{Sink, Stream};
+use futures_util::Sink;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
-use crate::WebSocketByteStream;
+use crate::{
+    byte_stream::{poll_read, Incoming, WsMessage},
+    WebSocketByteStream,
+};
+
+impl WsMessage for Message {
+    fn incoming(self) -> Incoming {
+        match self {
+            Message::Binary(data) => Incoming::Data(data),
+            Message::Close(_) => Incoming::Closed,
+            Message::Text(_) | Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {
+                Incoming::Skip
+            }
+        }
+    }
+}
 
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketByteStream<WebSocketStream<T>> {
     fn poll_read(
@@ -13,34 +28,13 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketByteStream<WebSoc
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         let projection = self.project();
-        let res = projection.socket.poll_next(cx);
-        match res {
-            Poll::Ready(data) => match data {
-                None | Some(Ok(Message::Close(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionReset,
-                    "Web socket closed",
-                ))),
-                Some(Err(e)) => Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, e))),
-                Some(Ok(Message::Binary(b))) => {
-                    buf.put_slice(&b);
-                    Poll::Ready(Ok(()))
-                }
-                Some(Ok(Message::Text(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received text kind data",
-                ))),
-                Some(Ok(Message::Ping(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received ping",
-                ))),
-                Some(Ok(Message::Pong(_))) => Poll::Ready(Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "WebSocket received pong",
-                ))),
-                Some(Ok(Message::Frame(_))) => unreachable!(),
-            },
-            Poll::Pending => Poll::Pending,
-        }
+        poll_read(
+            projection.socket,
+            projection.pending,
+            projection.read,
+            cx,
+            buf,
+        )
     }
 }
 
@@ -0,0 +1,30 @@
+use futures_util::SinkExt;
+use secure_comms::WebSocketByteStream;
+use tokio::io::AsyncReadExt;
+use tokio_tungstenite::{
+    tungstenite::{protocol::Role, Message},
+    WebSocketStream,
+};
+
+#[tokio::test]
+async fn large_messages_and_pings() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut sender = WebSocketStream::from_raw_socket(a, Role::Client, None).await;
+    let receiver = WebSocketStream::from_raw_socket(b, Role::Server, None).await;
+    let mut stream = WebSocketByteStream::new(receiver);
+
+    let data = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    sender.send(Message::Ping(vec![1])).await.unwrap();
+    sender.send(Message::Binary(data.clone())).await.unwrap();
+    sender.send(Message::Pong(vec![2])).await.unwrap();
+    sender.send(Message::Binary(vec![42])).await.unwrap();
+
+    let mut received = Vec::new();
+    let mut chunk = [0; 64];
+    while received.len() < data.len() + 1 {
+        let n = stream.read(&mut chunk).await.unwrap();
+        received.extend_from_slice(&chunk[..n]);
+    }
+    assert_eq!(&received[..data.len()], data);
+    assert_eq!(received[data.len()], 42);
+}
@@ -0,0 +1,68 @@
+use futures_util::{SinkExt, StreamExt};
+use secure_comms::{Codec, DataStream, StreamConfig};
+use serde::{Deserialize, Serialize};
+use tokio::io::DuplexStream;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Old {
+    name: String,
+}
+
+/// [`Old`] with a field added by a newer peer
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct New {
+    name: String,
+    priority: u32,
+}
+
+fn config(codecs: Vec<Codec>) -> StreamConfig {
+    StreamConfig {
+        codecs,
+        ..StreamConfig::default()
+    }
+}
+
+async fn pair<A, B>(
+    a: Vec<Codec>,
+    b: Vec<Codec>,
+) -> std::io::Result<(DataStream<DuplexStream, A>, DataStream<DuplexStream, B>)> {
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let (a, b) = (config(a), config(b));
+    let (x, y) = tokio::join!(DataStream::negotiate(x, &a), DataStream::negotiate(y, &b));
+    Ok((x?, y?))
+}
+
+#[tokio::test]
+async fn negotiated_codecs() {
+    // Each side sends in its own preferred codec
+    let (mut a, mut b) = pair::<String, String>(
+        vec![Codec::Cbor, Codec::Json],
+        vec![Codec::Json, Codec::Cbor],
+    )
+    .await
+    .unwrap();
+    a.send("hello".to_string()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), "hello");
+    b.send("world".to_string()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), "world");
+
+    let (mut new, mut old) = pair::<New, Old>(vec![Codec::MessagePack], vec![Codec::MessagePack])
+        .await
+        .unwrap();
+    new.send(New {
+        name: "server1".to_string(),
+        priority: 3,
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        old.next().await.unwrap().unwrap(),
+        Old {
+            name: "server1".to_string()
+        }
+    );
+
+    assert!(pair::<(), ()>(vec![Codec::Bincode], vec![Codec::Json])
+        .await
+        .is_err());
+}
@@ -0,0 +1,41 @@
+use futures_util::{SinkExt, StreamExt};
+use secure_comms::{Compression, CompressionConfig, DataStream, StreamConfig};
+use tokio::io::DuplexStream;
+
+type Stream = DataStream<DuplexStream, String>;
+
+async fn pair(a: StreamConfig, b: StreamConfig) -> (Stream, Stream) {
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let (x, y) = tokio::join!(DataStream::negotiate(x, &a), DataStream::negotiate(y, &b));
+    (x.unwrap(), y.unwrap())
+}
+
+#[tokio::test]
+async fn negotiated_compression() {
+    let config = |algorithms| StreamConfig {
+        compression: CompressionConfig {
+            algorithms,
+            threshold: 16,
+        },
+        ..StreamConfig::default()
+    };
+    let (mut a, mut b) = pair(
+        config(vec![Compression::Zstd, Compression::Deflate]),
+        config(vec![Compression::Deflate]),
+    )
+    .await;
+    let large = "config ".repeat(1000);
+    for msg in ["small", &large] {
+        a.send(msg.to_string()).await.unwrap();
+        assert_eq!(b.next().await.unwrap().unwrap(), msg);
+        b.send(msg.to_string()).await.unwrap();
+        assert_eq!(a.next().await.unwrap().unwrap(), msg);
+    }
+
+    // A side offering nothing still reads what the other sends
+    let (mut a, mut b) = pair(config(vec![Compression::Zstd]), config(Vec::new())).await;
+    a.send(large.clone()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), large);
+    b.send(large.clone()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), large);
+}
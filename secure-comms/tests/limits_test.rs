@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use secure_comms::{
+    handshake_deadline, Compression, CompressionConfig, DataStream, DataStreamError, StreamConfig,
+};
+use tokio::io::DuplexStream;
+
+#[tokio::test]
+async fn oversized_messages() {
+    let config = |max_message_size| StreamConfig {
+        compression: CompressionConfig {
+            algorithms: vec![Compression::Zstd],
+            threshold: 0,
+        },
+        max_message_size,
+        ..StreamConfig::default()
+    };
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let (large, small) = (config(1 << 20), config(1024));
+    let (big, little) = tokio::join!(
+        DataStream::negotiate(x, &large),
+        DataStream::negotiate(y, &small)
+    );
+    let mut big: DataStream<DuplexStream, Vec<u8>> = big.unwrap();
+    let mut little: DataStream<DuplexStream, Vec<u8>> = little.unwrap();
+
+    assert!(matches!(
+        little.send(vec![0; 4096]).await,
+        Err(DataStreamError::TooLarge(_))
+    ));
+    // Compressed it's small, but not once decompressed
+    big.send(vec![0; 4096]).await.unwrap();
+    assert!(little.next().await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn handshake_timeout() {
+    // The peer never answers the offers
+    let (x, _y) = tokio::io::duplex(1 << 16);
+    let config = StreamConfig::default();
+    let res = handshake_deadline(
+        Duration::from_millis(50),
+        DataStream::<_, ()>::negotiate(x, &config),
+    )
+    .await;
+    assert_eq!(res.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+}
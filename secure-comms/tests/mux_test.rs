@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use secure_comms::{Channel, Codec, DataStream, DataStreamError, Multiplexer, StreamConfig};
+
+async fn pair() -> (Multiplexer, Multiplexer) {
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let config = StreamConfig::default();
+    let (x, y) = tokio::join!(
+        DataStream::negotiate(x, &config),
+        DataStream::negotiate(y, &config)
+    );
+    (Multiplexer::new(x.unwrap()), Multiplexer::new(y.unwrap()))
+}
+
+#[tokio::test]
+async fn independent_channels() {
+    let (a, b) = pair().await;
+    let (bulk, _) = a.channel::<(), Vec<u8>>(Channel::Bulk).unwrap();
+    let (control, _) = a.channel::<(), String>(Channel::Control).unwrap();
+    let (_, mut bulk_in) = b.channel::<Vec<u8>, ()>(Channel::Bulk).unwrap();
+    let (_, mut control_in) = b.channel::<String, ()>(Channel::Control).unwrap();
+    assert!(b.channel::<String, ()>(Channel::Control).is_none());
+
+    // Nobody reads the bulk channel until its window is full, each message takes 7 chunks
+    let large = vec![7; 100_000];
+    let fitting = secure_comms::WINDOW / 7;
+    let filling = tokio::spawn(async move {
+        for _ in 0..=fitting {
+            bulk.send(large.clone()).await.unwrap();
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!filling.is_finished());
+
+    control.send("ping".to_string()).await.unwrap();
+    assert_eq!(control_in.next().await.unwrap().unwrap(), "ping");
+
+    // Taking a message makes room for the last one
+    assert_eq!(bulk_in.next().await.unwrap().unwrap().len(), 100_000);
+    filling.await.unwrap();
+    for _ in 0..fitting {
+        assert_eq!(bulk_in.next().await.unwrap().unwrap(), vec![7; 100_000]);
+    }
+}
+
+#[tokio::test]
+async fn readable_json() {
+    let (x, y) = tokio::io::duplex(1 << 16);
+    let config = StreamConfig {
+        codecs: vec![Codec::Json],
+        max_message_size: 64 * 1024,
+        ..StreamConfig::default()
+    };
+    let (x, y) = tokio::join!(
+        DataStream::negotiate(x, &config),
+        DataStream::negotiate(y, &config)
+    );
+    let (a, b) = (Multiplexer::new(x.unwrap()), Multiplexer::new(y.unwrap()));
+    let (sender, _) = a.channel::<(), String>(Channel::Bulk).unwrap();
+    let (_, mut receiver) = b.channel::<String, ()>(Channel::Bulk).unwrap();
+
+    // Split over several frames, with characters of several bytes and some to escape
+    let text = "😀".repeat(9000) + "\"\\ñ€";
+    sender.send(text.clone()).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), receiver.next()).await;
+    assert_eq!(received.unwrap().unwrap().unwrap(), text);
+}
+
+#[tokio::test]
+async fn try_send_without_room() {
+    let (a, b) = pair().await;
+    let (control, _) = a.channel::<(), u32>(Channel::Control).unwrap();
+    let (_, mut control_in) = b.channel::<u32, ()>(Channel::Control).unwrap();
+    for i in 0..secure_comms::WINDOW as u32 {
+        control.try_send(i).unwrap();
+    }
+    assert!(matches!(control.try_send(0), Err(DataStreamError::Full)));
+
+    // Room comes back once the peer takes a message
+    assert_eq!(control_in.next().await.unwrap().unwrap(), 0);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while control.try_send(1).is_err() {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn messages_larger_than_the_window() {
+    let (a, b) = pair().await;
+    let (bulk, _) = a.channel::<(), Vec<u8>>(Channel::Bulk).unwrap();
+    let (_, mut bulk_in) = b.channel::<Vec<u8>, ()>(Channel::Bulk).unwrap();
+
+    // It takes the whole window, so nothing else fits until the peer takes it
+    let huge = vec![1; 4 << 20];
+    bulk.send(huge.clone()).await.unwrap();
+    assert!(matches!(bulk.try_send(vec![2]), Err(DataStreamError::Full)));
+    let received = tokio::time::timeout(Duration::from_secs(5), bulk_in.next()).await;
+    assert_eq!(received.unwrap().unwrap().unwrap(), huge);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while bulk.try_send(vec![2]).is_err() {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+}
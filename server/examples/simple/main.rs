@@ -1,9 +1,18 @@
-use std::fs::File;
-
 #[tokio::main]
 async fn main() {
-    let config =
-        serde_json::from_reader(File::open(std::env::args().nth(1).unwrap()).unwrap()).unwrap();
     tracing_subscriber::fmt().compact().init();
-    server::start(config).await
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.get(1).map(String::as_str) {
+        Some("lock") => server::lock_plugins(&args[2]).await,
+        Some("migrate") => server::migrate_config(&args[2]),
+        Some("ca") => server::init_ca(&args[2], args.get(3).map_or("Cluster CA", String::as_str)),
+        Some("issue") => server::issue_cert(&args[2], &args[3]),
+        Some("token") => server::join_token(&args[2]),
+        Some("join") => server::join_cluster(&args[2], &args[3], &args[4]).await,
+        Some("key") if args[2] == "generate" => server::generate_key(&args[3]),
+        Some("secret") if args[2] == "encrypt" => {
+            server::encrypt_secret(&args[3], args.get(4).map(String::as_str))
+        }
+        _ => server::start_from_file(&args[1]).await,
+    }
 }
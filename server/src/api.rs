@@ -1,71 +1,215 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, net::SocketAddr};
 
+use async_trait::async_trait;
 use axum::{
-    extract::{State, WebSocketUpgrade, Path},
-    response::Response,
-    routing::get,
+    extract::{ConnectInfo, State, WebSocketUpgrade, Path},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Router, Json,
 };
 use chatter_protocol::ChatterMessage;
-use config::Param;
-use tracing::{error, info};
+use config::{Param, Resources, Role};
+use secure_comms::{handshake_deadline, AcceptError, Acceptor, Peer, WebSocketByteStream};
+use tokio::net::TcpStream;
+use tracing::{error, info, warn};
 
 use crate::{
+    ca::CaError,
+    enroll::{enroll, EnrollError, JoinRequest, JoinResponse},
+    handshakes::HandshakeGuard,
+    jobs::{self, PlaceError},
     node_manager::{
         event_triggers::{EventHandlers, FromErrors},
-        Connection, ConnectionError,
+        stream_config, ChatterStream, Connection, ConnectionError, NodeManager,
     },
+    listen::Chatter,
+    live_config::ReloadError,
+    tls::LiveTls,
+    verify::{warning, CertInfo},
     AppState,
 };
 
-async fn chatter<Ev>(State(state): State<AppState<Ev>>, ws: WebSocketUpgrade) -> Response
+async fn chatter<Ev>(
+    State(state): State<AppState<Ev>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    ws: WebSocketUpgrade,
+) -> Response
 where
     Ev: EventHandlers + Send + Sync + 'static,
     ConnectionError: FromErrors<Ev>,
 {
+    // Peers on a Unix socket are on this host, so they aren't limited
+    let guard = match peer {
+        Some(ConnectInfo(peer)) => match handshake(&state, peer) {
+            Some(guard) => Some(guard),
+            None => return StatusCode::TOO_MANY_REQUESTS.into_response(),
+        },
+        None => None,
+    };
     ws.on_upgrade(|ws| async move {
         tokio::spawn(async move {
-            let (s, name) = state.acceptor.accept_with_server_name(ws).await.unwrap();
-            if let Some(name) = name {
-                info!("Connected to {}", name);
-                let connection =
-                    Connection::accepted(s, state.config.clone(), state.ev.clone(), name.clone());
-                let connected = state
-                    .node_manager
-                    .read()
-                    .await
-                    .connected()
-                    .map(|s| s.to_string())
-                    .collect();
-                let msg = ChatterMessage::Hello {
-                    config: state.config.general.clone(),
-                    priority: state.config.node.priority,
-                    connected,
-                };
-                connection.send(msg).await.unwrap();
-                state.node_manager.write().await.up(name, connection)
-            } else {
-                error!("NO NAME IN CERTIFICATE PROVIDED");
-            }
+            let accepted = acceptor(&state)
+                .accept_tls(WebSocketByteStream::new(ws))
+                .await;
+            drop(guard);
+            accept(state, accepted).await;
         });
     })
 }
 
+/// Counts a handshake from the peer, unless it has too many in progress already
+fn handshake<Ev>(state: &AppState<Ev>, peer: SocketAddr) -> Option<HandshakeGuard> {
+    let limit = state.config.get().node.limits.handshakes_per_ip;
+    let guard = state.handshakes.start(peer.ip(), limit);
+    if guard.is_none() {
+        warn!("Refused a connection from {peer}, which has too many handshakes in progress");
+    }
+    guard
+}
+
+fn acceptor<Ev>(state: &AppState<Ev>) -> Acceptor {
+    Acceptor::from(state.tls.server()).with_config(stream_config(&state.config.get().node))
+}
+
+/// Takes a connection from a peer into the node manager, whichever transport it came over
+async fn accept<Ev>(state: AppState<Ev>, accepted: Result<(ChatterStream, Peer), AcceptError>)
+where
+    Ev: EventHandlers + Send + Sync + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    let (s, peer) = match accepted {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            warn!("Rejected a connection: {e}");
+            return;
+        }
+    };
+    let config = state.config.get();
+    // The handshake already checked the certificate names exactly one configured node
+    if let Some(name) = state.tls.identity(&peer.certificate) {
+        info!("Connected to {}", name);
+        let connection = Connection::new(s, state.config.clone(), state.ev.clone(), name.clone());
+        let connected = state
+            .node_manager
+            .read()
+            .await
+            .connected()
+            .map(|s| s.to_string())
+            .collect();
+        let msg = ChatterMessage::Hello {
+            config: config.general.clone(),
+            priority: config.node.priority,
+            connected,
+            lock_hash: state.config.lock_hash(),
+            advertise: config.node.advertise.clone(),
+        };
+        connection.send(msg).await.unwrap();
+        state.node_manager.write().await.up(name, connection)
+    } else {
+        error!(
+            "Rejected a connection from {:?}, which is no configured node",
+            peer.names
+        );
+    }
+}
+
+#[async_trait]
+impl<Ev> Chatter for AppState<Ev>
+where
+    Ev: EventHandlers + Send + Sync + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    fn tls(&self) -> &LiveTls {
+        &self.tls
+    }
+
+    async fn accept_tls(&self, tcp: TcpStream, peer: SocketAddr) {
+        let Some(guard) = handshake(self, peer) else {
+            return;
+        };
+        let accepted = acceptor(self).accept_tls(tcp).await;
+        drop(guard);
+        accept(self.clone(), accepted).await;
+    }
+
+    async fn accept_quic(&self, connecting: quinn::Connecting) {
+        let Some(guard) = handshake(self, connecting.remote_address()) else {
+            return;
+        };
+        let acceptor = acceptor(self);
+        let timeout = self.config.get().node.limits.handshake_timeout();
+        let accepted = handshake_deadline(timeout, async {
+            let io = secure_comms::accept_quic(connecting).await?;
+            acceptor.established(io).await
+        })
+        .await;
+        drop(guard);
+        accept(self.clone(), accepted).await;
+    }
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 enum NodeStatus {
     Connected, Disconnected
 }
 
-async fn nodes<Ev>(State(state): State<AppState<Ev>>) -> Json<HashMap<String, NodeStatus>>
+#[derive(serde::Serialize)]
+struct NodeInfo {
+    status: NodeStatus,
+    role: Role,
+    labels: HashMap<String, String>,
+    resources: Option<Resources>,
+}
+
+async fn nodes<Ev>(State(state): State<AppState<Ev>>) -> Json<HashMap<String, NodeInfo>>
 where
     Ev: Send + Sync + EventHandlers + 'static,
     ConnectionError: FromErrors<Ev>,
 {
-    Json(state.node_manager.read().await.nodes().map(|(name, status)| (name.to_string(), match status {
-        crate::node_manager::NodeStatus::Down | crate::node_manager::NodeStatus::Unknown => NodeStatus::Disconnected,
-        crate::node_manager::NodeStatus::Up(_) => NodeStatus::Connected,
-    })).collect())
+    let config = state.config.get();
+    Json(state.node_manager.read().await.nodes().map(|(name, status)| {
+        let node = config.general.node(name);
+        (name.to_string(), NodeInfo {
+            status: match status {
+                crate::node_manager::NodeStatus::Down | crate::node_manager::NodeStatus::Unknown => NodeStatus::Disconnected,
+                crate::node_manager::NodeStatus::Up(_) => NodeStatus::Connected,
+            },
+            role: node.map(|n| n.role).unwrap_or_default(),
+            labels: node.map(|n| n.labels.clone()).unwrap_or_default(),
+            resources: node.and_then(|n| n.resources.clone()),
+        })
+    }).collect())
+}
+
+#[derive(serde::Serialize)]
+struct CertStatus {
+    #[serde(flatten)]
+    cert: CertInfo,
+    expires_soon: bool,
+}
+
+#[derive(serde::Serialize)]
+struct Certificates {
+    own: Option<CertStatus>,
+    peers: Vec<CertStatus>,
+}
+
+async fn certificates<Ev>(State(state): State<AppState<Ev>>) -> Json<Certificates>
+where
+    Ev: Send + Sync + EventHandlers + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    let warning = warning(&state.config.get());
+    let status = |cert: CertInfo| CertStatus {
+        expires_soon: cert.expires_within(warning),
+        cert,
+    };
+    Json(Certificates {
+        own: state.tls.own_cert().map(status),
+        peers: state.tls.peer_certs().into_iter().map(status).collect(),
+    })
 }
 
 async fn jobs<Ev>(State(state): State<AppState<Ev>>) -> Json<Vec<String>>
@@ -73,26 +217,140 @@ async fn jobs<Ev>(State(state): State<AppState<Ev>>) -> Json<Vec<String>>
         Ev: Send + Sync + EventHandlers + 'static,
         ConnectionError: FromErrors<Ev>,
     {
-        Json(state.config.general.tasks.keys().cloned().collect())
+        Json(state.config.get().general.tasks.keys().cloned().collect())
     }
 
-async fn job<Ev>(State(state): State<AppState<Ev>>, Path(name): Path<String>) -> Json<()>
-    where
-        Ev: Send + Sync + EventHandlers + 'static,
-        ConnectionError: FromErrors<Ev>,
-    {
-        todo!();
-        Json(())
+/// Params the task takes, to build a submission from
+async fn job_params<Ev>(
+    State(state): State<AppState<Ev>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<Param>>, StatusCode>
+where
+    Ev: Send + Sync + EventHandlers + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    let config = state.config.get();
+    let task = config.general.tasks.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(task.params.clone()))
+}
+
+#[derive(serde::Serialize)]
+struct JobPlaced {
+    id: String,
+    node: String,
+}
+
+async fn job<Ev>(
+    State(state): State<AppState<Ev>>,
+    Path(name): Path<String>,
+    Json(params): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<Json<JobPlaced>, (StatusCode, String)>
+where
+    Ev: Send + Sync + EventHandlers + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    let Some(task) = state.config.get().general.tasks.get(&name).cloned() else {
+        return Err((StatusCode::NOT_FOUND, format!("Unknown task {name}")));
+    };
+    let params = task
+        .resolve_params(params)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid params: {e}")))?;
+    let id = jobs::new_id();
+    let node = state
+        .jobs
+        .place(id.clone(), name.clone(), task, params)
+        .await
+        .map_err(|e| {
+            let status = match e {
+                PlaceError::NoNode => StatusCode::SERVICE_UNAVAILABLE,
+                PlaceError::Send(..) | PlaceError::Refused(..) => StatusCode::BAD_GATEWAY,
+                PlaceError::NoAnswer(_) => StatusCode::GATEWAY_TIMEOUT,
+            };
+            error!(job = id, "Unable to place a job of {name}: {e}");
+            (status, e.to_string())
+        })?;
+    info!(job = id, "Placed a job of {name} on {node}");
+    Ok(Json(JobPlaced { id, node }))
+}
+
+/// Bootstrap of a new node, which has no certificate to chatter with yet
+async fn join<Ev>(
+    State(state): State<AppState<Ev>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<JoinRequest>,
+) -> Result<Json<JoinResponse>, (StatusCode, String)>
+where
+    Ev: Send + Sync + EventHandlers + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    // Limited like the chatter handshakes, as anyone can try to join
+    let _guard = match peer {
+        Some(ConnectInfo(peer)) => match handshake(&state, peer) {
+            Some(guard) => Some(guard),
+            None => {
+                return Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "too many joins in progress".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+    let name = request.name.clone();
+    let config = state.config.clone();
+    // The CA, the tokens and the config are files, read and written as the join goes
+    let enrolled = tokio::task::spawn_blocking(move || enroll(&config, &request))
+        .await
+        .expect("the enrollment doesn't panic");
+    let (response, diff) = enrolled.map_err(|e| {
+        let status = match e {
+            EnrollError::NoCa => StatusCode::NOT_FOUND,
+            EnrollError::InvalidToken => StatusCode::UNAUTHORIZED,
+            EnrollError::InvalidName(_)
+            | EnrollError::Ca(CaError::Certificate(_))
+            | EnrollError::Config(ReloadError::Invalid(_)) => StatusCode::BAD_REQUEST,
+            EnrollError::NodeExists(_) => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        error!("Refused the join of {name}: {e}");
+        (status, e.to_string())
+    })?;
+    info!("Enrolled {name} as a worker");
+    if let Some(diff) = diff {
+        NodeManager::broadcast(
+            &state.node_manager,
+            &ChatterMessage::GeneralConfigUpdate(diff),
+        )
+        .await;
+        if state.ev.clone().attempt_connect([name.as_str()]).await.is_err() {
+            error!("Error connecting to {name}");
+        }
     }
+    Ok(Json(response))
+}
 
+/// Routes the other nodes connect to
 #[allow(clippy::redundant_pub_crate)]
-pub(crate) fn api<Ev>() -> Router<AppState<Ev>>
+pub(crate) fn chatter_api<Ev>() -> Router<AppState<Ev>>
 where
     Ev: Send + Sync + EventHandlers + 'static,
     ConnectionError: FromErrors<Ev>,
 {
     Router::new()
         .route("/chatter", get(chatter))
+        .route("/join", post(join))
+}
+
+/// Public routes, for users and tools
+#[allow(clippy::redundant_pub_crate)]
+pub(crate) fn api<Ev>() -> Router<AppState<Ev>>
+where
+    Ev: Send + Sync + EventHandlers + 'static,
+    ConnectionError: FromErrors<Ev>,
+{
+    Router::new()
         .route("/nodes", get(nodes))
+        .route("/certificates", get(certificates))
         .route("/jobs", get(jobs))
+        .route("/jobs/:name", get(job_params).post(job))
 }
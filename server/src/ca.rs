@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+
+use config::NodeConfig;
+use source_cache::sha256_hex;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateSigningRequest, DistinguishedName,
+    DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use thiserror::Error;
+use time::{Duration, OffsetDateTime};
+
+/// Files of the CA inside its directory
+pub const CA_CERT_FILE: &str = "ca.crt";
+pub const CA_KEY_FILE: &str = "ca.key";
+
+const CA_VALIDITY: Duration = Duration::days(1826);
+const NODE_VALIDITY: Duration = Duration::days(730);
+
+#[derive(Debug, Error)]
+pub enum CaError {
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{0} already exists")]
+    Exists(PathBuf),
+    #[error(transparent)]
+    Certificate(#[from] rcgen::Error),
+}
+
+/// Certificate authority of a cluster, used to issue the node certificates
+pub struct Ca {
+    cert: Certificate,
+    pem: String,
+}
+
+impl Ca {
+    /// Creates a new CA in `dir`, refusing to replace an existing one
+    pub fn init(dir: &Path, name: &str) -> Result<Self, CaError> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now;
+        params.not_after = now + CA_VALIDITY;
+        let cert = Certificate::from_params(params)?;
+        let pem = cert.serialize_pem()?;
+
+        let cert_file = dir.join(CA_CERT_FILE);
+        let key_file = dir.join(CA_KEY_FILE);
+        for file in [&cert_file, &key_file] {
+            if file.exists() {
+                return Err(CaError::Exists(file.clone()));
+            }
+        }
+        create_dir(dir)?;
+        write(&key_file, &cert.serialize_private_key_pem(), true)?;
+        write(&cert_file, &pem, false)?;
+        Ok(Self { cert, pem })
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, CaError> {
+        let pem = read(&dir.join(CA_CERT_FILE))?;
+        let key = KeyPair::from_pem(&read(&dir.join(CA_KEY_FILE))?)?;
+        let cert = Certificate::from_params(CertificateParams::from_ca_cert_pem(&pem, key)?)?;
+        Ok(Self { cert, pem })
+    }
+
+    /// Certificate of the CA, in PEM
+    pub fn cert_pem(&self) -> &str {
+        &self.pem
+    }
+
+    /// Issues a certificate for the node, with its name as the DNS name other nodes check.
+    /// Returns the certificate and its key, in PEM
+    pub fn issue(&self, node: &str) -> Result<(String, String), CaError> {
+        let cert = Certificate::from_params(node_params(node))?;
+        Ok((
+            cert.serialize_pem_with_signer(&self.cert)?,
+            cert.serialize_private_key_pem(),
+        ))
+    }
+
+    /// Signs the certificate request of a node. Only its key is taken from the request,
+    /// the rest is what [`Ca::issue`] would give the node
+    pub fn sign_request(&self, csr: &str, node: &str) -> Result<String, CaError> {
+        let mut csr = CertificateSigningRequest::from_pem(csr)?;
+        let alg = csr.params.alg;
+        csr.params = node_params(node);
+        csr.params.alg = alg;
+        Ok(csr.serialize_pem_with_signer(&self.cert)?)
+    }
+
+    /// Issues a certificate for the node and writes it, its key and the CA certificate
+    /// where the node config expects them
+    pub fn install(&self, node: &NodeConfig) -> Result<(), CaError> {
+        let (cert, key) = self.issue(&node.name)?;
+        install(node, &self.pem, &cert, &key)
+    }
+}
+
+/// Writes the CA certificate, and the certificate and key of the node, where its config expects them
+pub fn install(node: &NodeConfig, ca: &str, cert: &str, key: &str) -> Result<(), CaError> {
+    for file in [&node.ca_file, &node.cert_file, &node.key_file] {
+        if let Some(dir) = file.parent() {
+            create_dir(dir)?;
+        }
+    }
+    write(&node.key_file, key, true)?;
+    write(&node.cert_file, cert, false)?;
+    write(&node.ca_file, ca, false)
+}
+
+/// SHA-256 fingerprint, in hex, of the first certificate in the PEM
+pub fn fingerprint(pem: &str) -> Option<String> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes()).ok()?;
+    certs.first().map(|der| sha256_hex(der))
+}
+
+/// Generates the key of a node and a request to certify it.
+/// Returns the request and the key, in PEM
+pub fn request(node: &str) -> Result<(String, String), CaError> {
+    // Requests can't carry the extensions, the CA adds them when signing
+    let mut params = CertificateParams::new(vec![node.to_string()]);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, node);
+    let cert = Certificate::from_params(params)?;
+    Ok((
+        cert.serialize_request_pem()?,
+        cert.serialize_private_key_pem(),
+    ))
+}
+
+fn node_params(node: &str) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, node);
+    params.subject_alt_names = vec![SanType::DnsName(node.to_string())];
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    // Nodes both accept and open connections
+    params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    params.use_authority_key_identifier_extension = true;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + NODE_VALIDITY;
+    params
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> CaError + '_ {
+    |source| CaError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn read(path: &Path) -> Result<String, CaError> {
+    std::fs::read_to_string(path).map_err(io_error(path))
+}
+
+fn create_dir(dir: &Path) -> Result<(), CaError> {
+    if dir == Path::new("") {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir).map_err(io_error(dir))
+}
+
+/// Writes the file, only readable by its owner if it is `private`.
+/// An existing file gets its permissions restricted too, before the contents are written
+fn write(path: &Path, contents: &str, private: bool) -> Result<(), CaError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(io_error(path))?;
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .map_err(io_error(path))?;
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    std::io::Write::write_all(&mut file, contents.as_bytes()).map_err(io_error(path))
+}
@@ -2,7 +2,7 @@ use std::{
     cell::OnceCell,
     fs::File,
     io::{self, BufReader},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use config::Config;
@@ -10,7 +10,7 @@ use rustls_pemfile::certs;
 use thiserror::Error;
 use tokio_rustls::rustls::{self, Certificate, PrivateKey};
 
-fn load_certs<P: AsRef<Path>>(path: P) -> io::Result<Vec<Certificate>> {
+pub(crate) fn load_certs<P: AsRef<Path>>(path: P) -> io::Result<Vec<Certificate>> {
     certs(&mut BufReader::new(File::open(path)?))
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
         .map(|certs| certs.into_iter().map(Certificate).collect())
@@ -62,6 +62,10 @@ pub enum CacheError {
     Rustls(#[from] rustls::Error),
     #[error("No key in file")]
     NoKey,
+    #[error("Invalid certificate revocation list {}", .0.display())]
+    InvalidCrl(PathBuf),
+    #[error("Certificate revocation list {} isn't signed by the CA", .0.display())]
+    UntrustedCrl(PathBuf),
 }
 
 impl CertificatesCache {
@@ -0,0 +1,236 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use config::{Config, GeneralConfigDiff, Node, Role};
+use ring::rand::{SecureRandom, SystemRandom};
+use source_cache::sha256_hex;
+use thiserror::Error;
+use tokio_rustls::rustls::ServerName;
+use tracing::error;
+use url::Url;
+
+use crate::{
+    ca::{self, Ca, CaError},
+    live_config::{LiveConfig, ReloadError},
+};
+
+/// File of the CA directory with the hashes of the unused join tokens, one per line
+pub const TOKENS_FILE: &str = "join_tokens";
+
+/// Tokens are consumed by rewriting the file, which mustn't happen twice at once
+static TOKENS: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Error)]
+pub enum EnrollError {
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Ca(#[from] CaError),
+    #[error("this node has no ca_dir to enroll nodes with")]
+    NoCa,
+    #[error("invalid or already used join token")]
+    InvalidToken,
+    #[error("{0} is not a valid node name")]
+    InvalidName(String),
+    #[error("there already is a node named {0}")]
+    NodeExists(String),
+    #[error("the node needs an advertise URL, or an entry in general.nodes, to be dialed at")]
+    NoAddress,
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("the cluster refused the join: {0}")]
+    Refused(String),
+    #[error("the CA sent by the cluster isn't the one of the join token")]
+    CaMismatch,
+    #[error(transparent)]
+    Config(#[from] ReloadError),
+}
+
+/// Sent by a new node to `/api/join`
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct JoinRequest {
+    /// The secret part of the join token
+    pub token: String,
+    pub name: String,
+    /// URL the other nodes dial the node at
+    pub address: Url,
+    /// Certificate request of the node, in PEM
+    pub csr: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct JoinResponse {
+    /// Certificate of the cluster CA, in PEM
+    pub ca: String,
+    /// Certificate of the new node, in PEM
+    pub cert: String,
+}
+
+fn tokens_file(ca_dir: &Path) -> PathBuf {
+    ca_dir.join(TOKENS_FILE)
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> EnrollError + '_ {
+    |source| EnrollError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Generates a join token, usable once, for the CA in `ca_dir`.
+/// The token is the secret and the fingerprint of the CA, joined by a dot, so the new node
+/// can tell it got the certificates from the right cluster
+pub fn create_token(ca_dir: &Path) -> Result<String, EnrollError> {
+    let ca = Ca::load(ca_dir)?;
+    let mut bytes = [0; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("unable to generate a random token");
+    let token = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+
+    add_token(ca_dir, &token)?;
+    let fingerprint = ca::fingerprint(ca.cert_pem()).expect("the CA certificate is valid PEM");
+    Ok(format!("{token}.{fingerprint}"))
+}
+
+/// Adds the token to the unused ones
+fn add_token(ca_dir: &Path, token: &str) -> Result<(), EnrollError> {
+    let path = tokens_file(ca_dir);
+    let _guard = TOKENS.lock().unwrap();
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path).map_err(io_error(&path))?;
+    writeln!(file, "{}", sha256_hex(token.trim().as_bytes())).map_err(io_error(&path))
+}
+
+/// Gives back a token consumed by a join that didn't happen
+pub fn restore_token(ca_dir: &Path, token: &str) -> Result<(), EnrollError> {
+    add_token(ca_dir, token)
+}
+
+/// Removes the token from the unused ones, failing if it wasn't there
+pub fn consume_token(ca_dir: &Path, token: &str) -> Result<(), EnrollError> {
+    let path = tokens_file(ca_dir);
+    let hash = sha256_hex(token.trim().as_bytes());
+    let _guard = TOKENS.lock().unwrap();
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(EnrollError::InvalidToken)
+        }
+        Err(e) => return Err(io_error(&path)(e)),
+    };
+    let mut found = false;
+    let rest = contents
+        .lines()
+        .filter(|line| {
+            let used = !found && line.trim() == hash;
+            found |= used;
+            !used
+        })
+        .map(|line| format!("{line}\n"))
+        .collect::<String>();
+    if !found {
+        return Err(EnrollError::InvalidToken);
+    }
+    std::fs::write(&path, rest).map_err(io_error(&path))
+}
+
+/// Checks the join request, signs the certificate of the new node and adds it to the config,
+/// returning the change to tell the peers about. The token is used up before anything else,
+/// so nothing is checked or signed without one, and given back if the join fails.
+/// The node joins as a worker with no labels, anything else is up to the config file
+pub fn enroll(
+    config: &LiveConfig,
+    request: &JoinRequest,
+) -> Result<(JoinResponse, Option<GeneralConfigDiff>), EnrollError> {
+    let current = config.get();
+    let ca_dir = current.node.ca_dir.as_deref().ok_or(EnrollError::NoCa)?;
+    consume_token(ca_dir, &request.token)?;
+    let enrolled = add_node(config, ca_dir, request);
+    if enrolled.is_err() {
+        if let Err(e) = restore_token(ca_dir, &request.token) {
+            error!(
+                "Unable to give back the join token of {}: {e}",
+                request.name
+            );
+        }
+    }
+    enrolled
+}
+
+fn add_node(
+    config: &LiveConfig,
+    ca_dir: &Path,
+    request: &JoinRequest,
+) -> Result<(JoinResponse, Option<GeneralConfigDiff>), EnrollError> {
+    let current = config.get();
+    let name = &request.name;
+    if ServerName::try_from(name.as_str()).is_err() {
+        return Err(EnrollError::InvalidName(name.clone()));
+    }
+    if current.general.node(name).is_some() {
+        return Err(EnrollError::NodeExists(name.clone()));
+    }
+    let node = Node {
+        address: request.address.clone().into(),
+        name: name.clone(),
+        role: Role::default(),
+        labels: Default::default(),
+        resources: None,
+        fingerprints: Vec::new(),
+    };
+    config.check_node(&node)?;
+    let ca = Ca::load(ca_dir)?;
+    let cert = ca.sign_request(&request.csr, name)?;
+    let diff = config.add_node(node)?;
+    let response = JoinResponse {
+        ca: ca.cert_pem().to_string(),
+        cert,
+    };
+    Ok((response, diff))
+}
+
+/// Joins the cluster through the node at `url`, writing the certificates
+/// where the config of this node expects them
+pub async fn join(config: &Config, url: &Url, token: &str) -> Result<(), EnrollError> {
+    let (token, fingerprint) = token
+        .trim()
+        .split_once('.')
+        .ok_or(EnrollError::InvalidToken)?;
+    let name = &config.node.name;
+    let address = match config.general.node(name) {
+        Some(node) => Url::clone(&node.address),
+        None => config.node.advertise.clone().ok_or(EnrollError::NoAddress)?,
+    };
+    let (csr, key) = ca::request(name)?;
+    let request = JoinRequest {
+        token: token.to_string(),
+        name: name.clone(),
+        address,
+        csr,
+    };
+    let url = url.join("api/join").expect("the join path is a valid URL");
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(&request)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(EnrollError::Refused(response.text().await?));
+    }
+    let response = response.json::<JoinResponse>().await?;
+    if !ca::fingerprint(&response.ca).is_some_and(|f| f.eq_ignore_ascii_case(fingerprint)) {
+        return Err(EnrollError::CaMismatch);
+    }
+    ca::install(&config.node, &response.ca, &response.cert, &key)?;
+    Ok(())
+}
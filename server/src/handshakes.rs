@@ -0,0 +1,44 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Handshakes in progress by IP address, so a single client can't tie up the node with them
+#[derive(Clone, Default)]
+pub struct Handshakes(Arc<Mutex<HashMap<IpAddr, usize>>>);
+
+/// Counts as a handshake in progress until dropped
+pub struct HandshakeGuard {
+    handshakes: Handshakes,
+    ip: IpAddr,
+}
+
+impl Handshakes {
+    /// Starts a handshake from the address, unless it has `limit` in progress already
+    pub fn start(&self, ip: IpAddr, limit: usize) -> Option<HandshakeGuard> {
+        let ip = ip.to_canonical();
+        let mut in_progress = self.0.lock().unwrap();
+        let count = in_progress.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(HandshakeGuard {
+            handshakes: self.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for HandshakeGuard {
+    fn drop(&mut self) {
+        let mut in_progress = self.handshakes.0.lock().unwrap();
+        if let Some(count) = in_progress.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_progress.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chatter_protocol::ChatterMessage;
+use config::{Node, TaskInfo};
+use runner::{Executor, SimplePrinter};
+use secure_comms::DataStreamError;
+use serde_json::Value;
+use task_balancer::{node, task, Balancer};
+use thiserror::Error;
+use tokio::sync::{oneshot, RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    live_config::LiveConfig,
+    node_manager::{NodeManager, NodeStatus},
+};
+
+#[derive(Debug, Error)]
+pub enum PlaceError {
+    #[error("No node that can run the task is up")]
+    NoNode,
+    #[error("Unable to send the job to {0}: {1}")]
+    Send(String, DataStreamError),
+    #[error("{0} refused the job: {1}")]
+    Refused(String, String),
+    #[error("{0} didn't answer whether it took the job")]
+    NoAnswer(String),
+}
+
+/// Why a node doesn't take a job placed on it
+#[derive(Debug, Error)]
+pub enum AcceptError {
+    #[error("Unknown task {0}")]
+    UnknownTask(String),
+    #[error("This node isn't allowed to run {0}")]
+    NotAllowed(String),
+}
+
+/// How long a node has to say whether it took a job placed on it
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn new_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Places jobs on the nodes of the cluster, and runs those placed on this one
+pub struct Jobs {
+    config: LiveConfig,
+    node_manager: Arc<RwLock<NodeManager>>,
+    executor: Arc<Executor>,
+    /// Jobs of this node that are running or waiting for resources
+    queued: AtomicUsize,
+    /// Jobs placed on other nodes that didn't say yet whether they took them
+    pending: Mutex<HashMap<String, oneshot::Sender<Option<String>>>>,
+}
+
+impl Jobs {
+    pub fn new(
+        config: LiveConfig,
+        node_manager: Arc<RwLock<NodeManager>>,
+        executor: Arc<Executor>,
+    ) -> Self {
+        Self {
+            config,
+            node_manager,
+            executor,
+            queued: AtomicUsize::new(0),
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Picks the node for a new job with the balancer among those up and allowed to run its task,
+    /// and hands the job to it. Returns the name of the node once it took the job
+    pub async fn place(
+        self: &Arc<Self>,
+        id: String,
+        name: String,
+        task: TaskInfo,
+        params: Vec<Value>,
+    ) -> Result<String, PlaceError> {
+        let config = self.config.get();
+        // The lock is only held to get the connections, sending waits for the peers
+        let connections = {
+            let node_manager = self.node_manager.read().await;
+            config
+                .general
+                .nodes
+                .iter()
+                .map(|node| match node_manager.get(&node.name).into_owned() {
+                    NodeStatus::Up(connection) => Some(connection),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut candidates = Vec::new();
+        for (index, node) in config.general.nodes.iter().enumerate() {
+            let (priority, queue_length) = if node.name == config.node.name {
+                (config.node.priority, self.queued.load(Ordering::Relaxed))
+            } else if let Some(connection) = &connections[index] {
+                (connection.priority().await, connection.queue_length().await)
+            } else {
+                continue;
+            };
+            candidates.push(Candidate {
+                index,
+                priority,
+                queue_length,
+                placed: None,
+            });
+        }
+        let placement = Placement {
+            task: &task,
+            nodes: &config.general.nodes,
+        };
+        Balancer::new(candidates.iter_mut().collect())
+            .enqueue(placement)
+            .map_err(|_| PlaceError::NoNode)?;
+        let index = candidates
+            .iter()
+            .find(|candidate| candidate.placed.is_some())
+            .map(|candidate| candidate.index)
+            .expect("the balancer placed the job");
+        let node = config.general.nodes[index].name.clone();
+        if node == config.node.name {
+            self.run(id, name, task, params);
+        } else if let Some(connection) = &connections[index] {
+            let (tx, rx) = oneshot::channel();
+            self.pending.lock().unwrap().insert(id.clone(), tx);
+            let msg = ChatterMessage::SendTask {
+                id: id.clone(),
+                task: name,
+                params,
+            };
+            let res = match connection.send(msg).await {
+                Ok(()) => match tokio::time::timeout(ANSWER_TIMEOUT, rx).await {
+                    Ok(Ok(None)) => Ok(()),
+                    Ok(Ok(Some(error))) => Err(PlaceError::Refused(node.clone(), error)),
+                    Ok(Err(_)) | Err(_) => Err(PlaceError::NoAnswer(node.clone())),
+                },
+                Err(e) => Err(PlaceError::Send(node.clone(), e)),
+            };
+            self.pending.lock().unwrap().remove(&id);
+            res?;
+        }
+        Ok(node)
+    }
+
+    /// Runs a job another node placed on this one, if this node can run its task
+    pub fn accept(
+        self: &Arc<Self>,
+        id: String,
+        name: String,
+        params: Vec<Value>,
+    ) -> Result<(), AcceptError> {
+        let config = self.config.get();
+        let Some(task) = config.general.tasks.get(&name).cloned() else {
+            return Err(AcceptError::UnknownTask(name));
+        };
+        let allowed = config
+            .general
+            .node(&config.node.name)
+            .is_some_and(|node| task.can_run_on_node(node));
+        if !allowed {
+            return Err(AcceptError::NotAllowed(name));
+        }
+        self.run(id, name, task, params);
+        Ok(())
+    }
+
+    /// The node a job was placed on answered whether it took it
+    pub fn accepted(&self, id: &str, error: Option<String>) {
+        if let Some(tx) = self.pending.lock().unwrap().remove(id) {
+            let _ = tx.send(error);
+        }
+    }
+
+    /// Runs a job on this node, keeping the other nodes up to date with its queue length
+    pub fn run(self: &Arc<Self>, id: String, name: String, task: TaskInfo, params: Vec<Value>) {
+        let length = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            jobs.queue_update(length).await;
+            info!(job = id, "Running {name}");
+            if let Err(e) = jobs
+                .executor
+                .execute(&name, &task, params, SimplePrinter)
+                .await
+            {
+                warn!(job = id, "Job failed: {e}");
+            }
+            let length = jobs.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+            jobs.queue_update(length).await;
+        });
+    }
+
+    async fn queue_update(&self, length: usize) {
+        let msg = ChatterMessage::QueueUpdate {
+            length: length.try_into().unwrap_or(u32::MAX),
+        };
+        NodeManager::broadcast(&self.node_manager, &msg).await;
+    }
+}
+
+/// A node the job can go to, by its index in the config
+struct Candidate<'a> {
+    index: usize,
+    priority: u32,
+    queue_length: usize,
+    placed: Option<Placement<'a>>,
+}
+
+struct Placement<'a> {
+    task: &'a TaskInfo,
+    nodes: &'a [Node],
+}
+
+impl task::Task for Placement<'_> {
+    type NodeId = usize;
+
+    fn can_run(&self, node: usize) -> bool {
+        self.task.can_run_on_node(&self.nodes[node])
+    }
+}
+
+impl<'a> node::Node for Candidate<'a> {
+    type Id = usize;
+    type Task = Placement<'a>;
+
+    fn send_task(&mut self, placement: Placement<'a>) {
+        self.placed = Some(placement);
+    }
+
+    fn queue_length(&self) -> usize {
+        self.queue_length
+    }
+
+    fn priority(&self) -> usize {
+        self.priority as usize
+    }
+
+    fn id(&self) -> usize {
+        self.index
+    }
+}
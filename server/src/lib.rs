@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use axum::{
     extract::{State, WebSocketUpgrade},
@@ -6,115 +6,368 @@ use axum::{
     routing::get,
     Router,
 };
-use cache::{CacheError, CertificatesCache};
 use chatter_protocol::ChatterMessage;
-use config::Config;
+use config::{ClusterKey, Config, Lockfile, CONFIG_VERSION};
+use handshakes::Handshakes;
+use jobs::Jobs;
+use live_config::LiveConfig;
 use node_manager::{
     event_triggers::{EventHandlers, EventHandlersImpl, FromErrors},
     Connection, ConnectionError, NodeManager,
 };
-use secure_comms::Acceptor;
+use plugin_manager::{install_locked, Resolver, VerifyError};
+use runner::Executor;
+use target_lexicon::Triple;
+use scheduler::Scheduler;
+use source_cache::SourceCache;
+use tls::LiveTls;
 use tokio::sync::RwLock;
-use tokio_rustls::rustls::{
-    server::AllowAnyAuthenticatedClient, ClientConfig, RootCertStore, ServerConfig,
-};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use url::Url;
 
 mod api;
+pub mod ca;
 mod cache;
+pub mod enroll;
+mod handshakes;
+mod jobs;
+mod listen;
+mod live_config;
 mod node_manager;
-
-fn server_config(
-    conf: &Config,
-    cache: &CertificatesCache,
-) -> Result<Arc<ServerConfig>, CacheError> {
-    let mut rcs = RootCertStore::empty();
-    for cert in cache.get_ca(conf)? {
-        rcs.add(cert)?;
-    }
-    let client_cert_verifier = AllowAnyAuthenticatedClient::new(rcs).boxed();
-    let config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_client_cert_verifier(client_cert_verifier)
-        .with_single_cert(cache.get_cert(conf)?, cache.get_key(conf)?)?;
-    Ok(Arc::new(config))
-}
-
-fn client_config(
-    conf: &Config,
-    cache: &CertificatesCache,
-) -> Result<Arc<ClientConfig>, CacheError> {
-    let mut rcs = RootCertStore::empty();
-    for cert in cache.get_ca(conf)? {
-        rcs.add(cert)?;
-    }
-    let config = ClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(rcs)
-        .with_client_auth_cert(cache.get_cert(conf)?, cache.get_key(conf)?)?;
-    Ok(Arc::new(config))
-}
+mod scheduler;
+mod tls;
+pub mod verify;
 
 struct AppState<Ev> {
-    acceptor: Arc<Acceptor>,
+    tls: LiveTls,
     node_manager: Arc<RwLock<NodeManager>>,
-    config: Arc<Config>, // client_config: Arc<ClientConfig>,
+    config: LiveConfig, // client_config: Arc<ClientConfig>,
     ev: Arc<Ev>,
+    handshakes: Handshakes,
+    jobs: Arc<Jobs>,
 }
 
 impl<Ev> Clone for AppState<Ev> {
     fn clone(&self) -> Self {
         Self {
-            acceptor: self.acceptor.clone(),
+            tls: self.tls.clone(),
             node_manager: self.node_manager.clone(),
             config: self.config.clone(),
             ev: self.ev.clone(),
+            handshakes: self.handshakes.clone(),
+            jobs: self.jobs.clone(),
         }
     }
 }
 
 pub async fn start(config: Config) {
-    let config = Arc::new(config);
-    let api = api::api();
-    let cache = CertificatesCache::default();
-    let client_config = client_config(&config, &cache).unwrap();
-    let app = Router::new()
-        .route("/", get(|| async { "Hello, World!" }))
-        .nest("/api", api);
+    run(config, None).await
+}
+
+/// Starts the server, reloading the config when the file changes
+pub async fn start_from_file<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    let config = match live_config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Unable to load config from {}: {e}", path.display());
+            return;
+        }
+    };
+    run(config, Some(path)).await
+}
+
+/// Resolves the plugins of the config file to exact builds, writing them to the node's lockfile
+pub async fn lock_plugins<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    let config = match live_config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Unable to load config from {}: {e}", path.display());
+            return;
+        }
+    };
+    let cache = SourceCache::new(&config.node.cache_dir);
+    let lockfile = match Resolver::new(&config.general.repositories, &cache)
+        .lock(&config.general.plugins)
+        .await
+    {
+        Ok(lockfile) => lockfile,
+        Err(e) => {
+            error!("Unable to resolve the plugins: {e}");
+            return;
+        }
+    };
+    match lockfile.save(&config.node.lock_file) {
+        Ok(()) => info!(
+            "Locked {} plugins in {}",
+            lockfile.plugins.len(),
+            config.node.lock_file.display()
+        ),
+        Err(e) => error!("Unable to write {}: {e}", config.node.lock_file.display()),
+    }
+}
+
+/// Fetches the locked build of every plugin for this host, checking it against the lockfile
+pub(crate) async fn install_plugins(
+    config: &Config,
+    lockfile: &Lockfile,
+) -> Result<(), VerifyError> {
+    let cache = SourceCache::new(&config.node.cache_dir);
+    let host = Triple::host();
+    for name in lockfile.plugins.keys() {
+        let path = install_locked(lockfile, name, &cache, &host).await?;
+        info!("Installed plugin {name} from {}", path.display());
+    }
+    Ok(())
+}
+
+/// Rewrites the config file in the latest format version
+pub fn migrate_config<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    match config::migrate_file(&path) {
+        Ok(CONFIG_VERSION) => info!("{} is already up to date", path.display()),
+        Ok(version) => info!(
+            "Migrated {} from version {version} to {CONFIG_VERSION}",
+            path.display()
+        ),
+        Err(e) => error!("Unable to migrate {}: {e}", path.display()),
+    }
+}
+
+/// Creates the cluster CA in `dir`
+pub fn init_ca<P: Into<PathBuf>>(dir: P, name: &str) {
+    let dir = dir.into();
+    match ca::Ca::init(&dir, name) {
+        Ok(_) => info!("Created the CA {name} in {}", dir.display()),
+        Err(e) => error!("Unable to create the CA: {e}"),
+    }
+}
+
+/// Issues the certificate of the node configured in `config` with the CA in `ca_dir`,
+/// writing it, its key and the CA certificate to the files of the config
+pub fn issue_cert<P: Into<PathBuf>, Q: Into<PathBuf>>(ca_dir: P, config: Q) {
+    let (ca_dir, path) = (ca_dir.into(), config.into());
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Unable to load config from {}: {e}", path.display());
+            return;
+        }
+    };
+    match ca::Ca::load(&ca_dir).and_then(|ca| ca.install(&config.node)) {
+        Ok(()) => info!(
+            "Issued the certificate of {} to {}",
+            config.node.name,
+            config.node.cert_file.display()
+        ),
+        Err(e) => error!("Unable to issue the certificate of {}: {e}", config.node.name),
+    }
+}
+
+/// Prints a new join token for the CA in `ca_dir`
+pub fn join_token<P: Into<PathBuf>>(ca_dir: P) {
+    match enroll::create_token(&ca_dir.into()) {
+        Ok(token) => println!("{token}"),
+        Err(e) => error!("Unable to create a join token: {e}"),
+    }
+}
+
+/// Generates the cluster key the nodes decrypt the secrets of the config with, in `path`
+pub fn generate_key<P: Into<PathBuf>>(path: P) {
+    let path = path.into();
+    match ClusterKey::create(&path) {
+        Ok(_) => info!("Created the cluster key {}", path.display()),
+        Err(e) => error!("Unable to create the cluster key: {e}"),
+    }
+}
+
+/// Prints the value encrypted with the cluster key in `key_file`, to use as an encrypted secret.
+/// Without a value it is read from stdin, which keeps it out of the shell history
+pub fn encrypt_secret<P: Into<PathBuf>>(key_file: P, value: Option<&str>) {
+    let path = key_file.into();
+    let key = match ClusterKey::load(&path) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Unable to load the cluster key: {e}");
+            return;
+        }
+    };
+    let value = match value {
+        Some(value) => value.to_string(),
+        None => match std::io::read_to_string(std::io::stdin()) {
+            Ok(value) => value.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => {
+                error!("Unable to read the secret: {e}");
+                return;
+            }
+        },
+    };
+    println!("{}", key.encrypt(&value));
+}
+
+/// Enrolls the node configured in `config` through the cluster node at `url`
+pub async fn join_cluster<P: Into<PathBuf>>(config: P, url: &str, token: &str) {
+    let path = config.into();
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Unable to load config from {}: {e}", path.display());
+            return;
+        }
+    };
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Invalid URL {url}: {e}");
+            return;
+        }
+    };
+    if url.scheme() == "http" {
+        warn!("Joining over plain HTTP, the join token can be read on the way");
+    }
+    match enroll::join(&config, &url, token).await {
+        Ok(()) => info!("{} joined the cluster", config.node.name),
+        Err(e) => error!("Unable to join the cluster: {e}"),
+    }
+}
+
+async fn run(config: Config, path: Option<PathBuf>) {
+    let diagnostics = config.validate();
+    for diagnostic in diagnostics.warnings() {
+        warn!("Config {diagnostic}");
+    }
+    for diagnostic in diagnostics.errors() {
+        error!("Config {diagnostic}");
+    }
+    if diagnostics.has_errors() {
+        error!("Invalid config, refusing to start");
+        return;
+    }
+    let lockfile = match live_config::read_lockfile(&config) {
+        Ok(lockfile) => lockfile,
+        Err(e) => {
+            error!("Unable to use {}: {e}", config.node.lock_file.display());
+            return;
+        }
+    };
+    if let Some(lockfile) = &lockfile {
+        if let Err(e) = install_plugins(&config, lockfile).await {
+            error!("Unable to install the plugins: {e}");
+            return;
+        }
+    }
+    let config = match &path {
+        Some(path) => LiveConfig::new(config).with_file(path),
+        None => LiveConfig::new(config),
+    }
+    .with_lockfile(lockfile.as_ref());
+    let initial = config.get();
+    let tls = match LiveTls::load(&initial) {
+        Ok(tls) => tls,
+        Err(e) => {
+            error!("Unable to load the certificates: {e}");
+            return;
+        }
+    };
     let node_manager = NodeManager::default();
     let node_manager = Arc::new(RwLock::new(node_manager));
+    let cluster_key = match initial.node.cluster_key_file.as_deref().map(ClusterKey::load) {
+        None => None,
+        Some(Ok(key)) => Some(key),
+        Some(Err(e)) => {
+            error!("Unable to load the cluster key: {e}");
+            return;
+        }
+    };
+    let executor = Arc::new(Executor::new(
+        initial
+            .general
+            .node(&initial.node.name)
+            .and_then(|node| node.resources.clone()),
+        cluster_key,
+        SourceCache::new(&initial.node.cache_dir),
+    ));
+    let jobs = Arc::new(Jobs::new(config.clone(), node_manager.clone(), executor));
+    let scheduler = Arc::new(Scheduler::new(
+        config.clone(),
+        node_manager.clone(),
+        jobs.clone(),
+    ));
     let ev = Arc::new(EventHandlersImpl::new(
         config.clone(),
-        client_config.clone(),
+        tls.clone(),
         node_manager.clone(),
+        scheduler.clone(),
+        jobs.clone(),
     ));
-    for node in config
+    for node in initial
         .general
         .nodes
         .iter()
-        .filter(|n| n.name != config.node.name)
+        .filter(|n| n.name != initial.node.name)
     {
-        node_manager
-            .write()
-            .await
-            .connect(node, client_config.clone(), config.clone(), ev.clone())
-            .await;
-    }
-
-    let server_config = server_config(&config, &cache).unwrap();
-
-    // run it with hyper on localhost:3000
-    info!("Server started at {}", config.node.addr);
-    axum::Server::bind(&config.node.addr)
-        .serve(
-            app.with_state(AppState {
-                acceptor: Arc::new(Acceptor::from(server_config)),
-                node_manager,
-                config, // client_config,
-                ev,
-            })
-            .into_make_service(),
+        NodeManager::connect(
+            &node_manager,
+            node,
+            tls.client(),
+            config.clone(),
+            ev.clone(),
         )
-        .await
-        .unwrap();
+        .await;
+    }
+
+    let _watcher = match path {
+        Some(path) => {
+            match live_config::watch_file(path, config.clone(), node_manager.clone(), ev.clone()) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    error!("Unable to watch the config file, it won't be reloaded: {e}");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    tokio::spawn(scheduler.run());
+    tokio::spawn(tls::monitor_expiry(tls.clone(), config.clone()));
+
+    let _tls_watcher = match tls::watch_files(tls.clone(), config.clone(), node_manager.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            error!("Unable to watch the certificates, they won't be reloaded: {e}");
+            None
+        }
+    };
+
+    let state = AppState {
+        tls,
+        node_manager,
+        config, // client_config,
+        ev,
+        handshakes: Handshakes::default(),
+        jobs,
+    };
+    let chatter = Router::new()
+        .nest("/api", api::chatter_api())
+        .with_state(state.clone());
+    let public = Router::new()
+        .route("/", get(|| async { "Hello, World!" }))
+        .nest("/api", api::api())
+        .with_state(state.clone());
+    let peers: Arc<dyn listen::Chatter> = Arc::new(state);
+
+    let served = if initial.node.api_listen.is_empty() {
+        listen::serve_all(&initial.node.listen, chatter.merge(public), Some(peers)).await
+    } else {
+        futures_util::try_join!(
+            listen::serve_all(&initial.node.listen, chatter, Some(peers)),
+            listen::serve_all(&initial.node.api_listen, public, None),
+        )
+        .map(|_| ())
+    };
+    if let Err(e) = served {
+        error!("Unable to serve: {e}");
+    }
 }
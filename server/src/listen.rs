@@ -0,0 +1,173 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::Router;
+use config::ListenAddr;
+use futures_util::future::try_join_all;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::tls::LiveTls;
+
+/// Pause after a failed accept, so a lack of file descriptors doesn't spin the loop
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Takes the chatter connections of the listeners without HTTP
+#[async_trait]
+pub trait Chatter: Send + Sync {
+    fn tls(&self) -> &LiveTls;
+
+    /// Does the TLS handshake on a TCP connection and runs the chatter over it
+    async fn accept_tls(&self, tcp: TcpStream, peer: SocketAddr);
+
+    /// Waits for the QUIC handshake and the stream of the peer, and runs the chatter over it
+    async fn accept_quic(&self, connecting: quinn::Connecting);
+}
+
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error("{addr}: {source}")]
+    Io {
+        addr: ListenAddr,
+        source: std::io::Error,
+    },
+    #[error("{addr}: {source}")]
+    Http {
+        addr: ListenAddr,
+        source: hyper::Error,
+    },
+    #[cfg(not(unix))]
+    #[error("{0}: Unix domain sockets are not supported on this platform")]
+    UnixUnsupported(ListenAddr),
+    #[error("{0}: only HTTP can be served here")]
+    NotHttp(ListenAddr),
+}
+
+/// Serves the app on every address, until one of them fails.
+/// Without `chatter`, only the HTTP addresses are allowed
+pub async fn serve_all(
+    addrs: &[ListenAddr],
+    app: Router,
+    chatter: Option<Arc<dyn Chatter>>,
+) -> Result<(), ServeError> {
+    try_join_all(
+        addrs
+            .iter()
+            .map(|addr| serve(addr.clone(), app.clone(), chatter.clone())),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn serve(
+    addr: ListenAddr,
+    app: Router,
+    chatter: Option<Arc<dyn Chatter>>,
+) -> Result<(), ServeError> {
+    let http = |source| ServeError::Http {
+        addr: addr.clone(),
+        source,
+    };
+    let io = |source| ServeError::Io {
+        addr: addr.clone(),
+        source,
+    };
+    match &addr {
+        ListenAddr::Tcp(socket) => {
+            let server = axum::Server::try_bind(socket).map_err(http)?;
+            info!("Listening on {addr}");
+            // The address of the peers is needed to limit their handshakes
+            server
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .map_err(http)
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            // A socket left behind by a previous run would make the bind fail
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io(e)),
+                _ => {}
+            }
+            let listener = tokio::net::UnixListener::bind(path).map_err(io)?;
+            info!("Listening on {addr}");
+            hyper::Server::builder(unix::Accept(listener))
+                .serve(app.into_make_service())
+                .await
+                .map_err(http)
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => Err(ServeError::UnixUnsupported(addr.clone())),
+        ListenAddr::Tls(socket) => {
+            let chatter = chatter.ok_or_else(|| ServeError::NotHttp(addr.clone()))?;
+            let listener = TcpListener::bind(socket).await.map_err(io)?;
+            info!("Listening on {addr}");
+            loop {
+                let (tcp, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Running out of file descriptors or an aborted connection only
+                        // affect this attempt, the listener keeps going
+                        warn!("Unable to accept a connection on {addr}: {e}");
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                if let Err(e) = tcp.set_nodelay(true) {
+                    warn!("Unable to disable Nagle's algorithm: {e}");
+                }
+                let chatter = chatter.clone();
+                tokio::spawn(async move { chatter.accept_tls(tcp, peer).await });
+            }
+        }
+        ListenAddr::Quic(socket) => {
+            let chatter = chatter.ok_or_else(|| ServeError::NotHttp(addr.clone()))?;
+            let tls = chatter.tls();
+            let endpoint =
+                quinn::Endpoint::server(quinn::ServerConfig::with_crypto(tls.server()), *socket)
+                    .map_err(io)?;
+            // Reloaded certificates only apply to the connections after them
+            let mut reloads = tls.subscribe();
+            let reloaded = endpoint.clone();
+            tokio::spawn(async move {
+                while reloads.changed().await.is_ok() {
+                    let server = reloads.borrow().server.clone();
+                    reloaded.set_server_config(Some(quinn::ServerConfig::with_crypto(server)));
+                }
+            });
+            info!("Listening on {addr}");
+            while let Some(connecting) = endpoint.accept().await {
+                let chatter = chatter.clone();
+                tokio::spawn(async move { chatter.accept_quic(connecting).await });
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub struct Accept(pub UnixListener);
+
+    impl hyper::server::accept::Accept for Accept {
+        type Conn = UnixStream;
+        type Error = std::io::Error;
+
+        fn poll_accept(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+            self.0
+                .poll_accept(cx)
+                .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock as SyncRwLock},
+    time::Duration,
+};
+
+use chatter_protocol::ChatterMessage;
+use config::{
+    Config, Diagnostics, GeneralConfigDiff, Lockfile, LockfileError, MigrationError, Node,
+};
+use diff::Diff;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use plugin_manager::{check_locked, VerifyError};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::sync::{mpsc, watch, RwLock};
+use tracing::{error, info, warn};
+
+use crate::node_manager::{event_triggers::AttemptConnectHandler, NodeManager};
+
+/// Handle to the config of the running node, which can be swapped while running.
+/// Cloning it gives another handle to the same config
+#[derive(Clone)]
+pub struct LiveConfig {
+    tx: Arc<watch::Sender<Arc<Config>>>,
+    /// File the config was loaded from, where changes made while running are kept
+    file: Option<Arc<Path>>,
+    /// Hash of the plugin lockfile, read when the config is loaded
+    lock_hash: Arc<SyncRwLock<Option<String>>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+    #[error("Invalid config")]
+    Invalid(Diagnostics),
+    #[error("Changing {} requires a restart", .0.join(", "))]
+    RestartRequired(Vec<&'static str>),
+    #[error(transparent)]
+    Lockfile(#[from] LockfileError),
+    #[error("{0}, lock the plugins again")]
+    Locked(#[from] VerifyError),
+}
+
+/// What changed in a reload, to be told to the rest of the cluster
+#[derive(Debug)]
+pub struct ConfigUpdate {
+    pub general: Option<GeneralConfigDiff>,
+    pub priority: Option<u32>,
+    /// The new plugin lockfile, if it changed
+    pub lockfile: Option<Lockfile>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> Self {
+        Self {
+            tx: Arc::new(watch::channel(Arc::new(config)).0),
+            file: None,
+            lock_hash: Arc::default(),
+        }
+    }
+
+    pub fn with_file(mut self, path: &Path) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    pub fn with_lockfile(self, lockfile: Option<&Lockfile>) -> Self {
+        *self.lock_hash.write().unwrap() = lockfile.map(Lockfile::hash);
+        self
+    }
+
+    /// The current config. Hold on to it only as long as needed, it might get replaced
+    pub fn get(&self) -> Arc<Config> {
+        self.tx.borrow().clone()
+    }
+
+    /// Receiver notified every time the config is swapped
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.tx.subscribe()
+    }
+
+    /// Hash of the plugin lockfile of this node, as of the last load of the config
+    pub fn lock_hash(&self) -> Option<String> {
+        self.lock_hash.read().unwrap().clone()
+    }
+
+    /// Applies a general config change sent by a peer, unless it makes the config invalid.
+    /// The denied certificates are left as they are: a single peer denying the others
+    /// would split the cluster, so they only change from the config file of each node
+    pub fn apply_general_diff(&self, diff: &GeneralConfigDiff) -> Result<(), ReloadError> {
+        let mut result = Ok(());
+        self.tx.send_if_modified(|config| {
+            let mut new = Config::clone(config);
+            new.general.apply(diff);
+            if new.general.denied_certificates != config.general.denied_certificates {
+                warn!("Ignored a change of the denied certificates, they only change from the config file");
+                new.general.denied_certificates = config.general.denied_certificates.clone();
+            }
+            let diagnostics = new.validate();
+            if diagnostics.has_errors() {
+                result = Err(ReloadError::Invalid(diagnostics));
+                return false;
+            }
+            if new == **config {
+                return false;
+            }
+            *config = Arc::new(new);
+            true
+        });
+        result
+    }
+
+    /// Checks the config would still be valid with the node added
+    pub fn check_node(&self, node: &Node) -> Result<(), ReloadError> {
+        let mut new = Config::clone(&self.get());
+        new.general.nodes.push(node.clone());
+        let diagnostics = new.validate();
+        if diagnostics.has_errors() {
+            return Err(ReloadError::Invalid(diagnostics));
+        }
+        Ok(())
+    }
+
+    /// Adds a node to the general config and to the config file, returning the change
+    /// to tell the peers about. Nothing changes if there already is a node with its name
+    pub fn add_node(&self, node: Node) -> Result<Option<GeneralConfigDiff>, ReloadError> {
+        if self.get().general.node(&node.name).is_some() {
+            return Ok(None);
+        }
+        self.check_node(&node)?;
+        if let Some(path) = &self.file {
+            save_node(path, &node)?;
+        }
+        let mut diff = None;
+        self.tx.send_if_modified(|config| {
+            if config.general.node(&node.name).is_some() {
+                return false;
+            }
+            let mut new = Config::clone(config);
+            new.general.nodes.push(node.clone());
+            diff = Some(config.general.diff(&new.general));
+            *config = Arc::new(new);
+            true
+        });
+        Ok(diff)
+    }
+
+    /// Swaps in the new config if it is valid and doesn't change anything that needs a restart
+    pub fn reload(&self, new: Config) -> Result<ConfigUpdate, ReloadError> {
+        let diagnostics = new.validate();
+        for diagnostic in diagnostics.warnings() {
+            warn!("Config {diagnostic}");
+        }
+        if diagnostics.has_errors() {
+            return Err(ReloadError::Invalid(diagnostics));
+        }
+        let lockfile = read_lockfile(&new)?;
+        let lock_hash = lockfile.as_ref().map(Lockfile::hash);
+        let lockfile = lockfile.filter(|_| lock_hash != self.lock_hash());
+        let mut result = Ok(None);
+        self.tx.send_if_modified(|current| {
+            let restart = current.node.restart_required_changes(&new.node);
+            if !restart.is_empty() {
+                result = Err(ReloadError::RestartRequired(restart));
+                return false;
+            }
+            *self.lock_hash.write().unwrap() = lock_hash;
+            if current.general != new.general {
+                info!("Config changes:\n{}", current.general.changes(&new.general));
+            }
+            result = Ok(Some(ConfigUpdate {
+                general: (current.general != new.general)
+                    .then(|| current.general.diff(&new.general)),
+                priority: (current.node.priority != new.node.priority).then_some(new.node.priority),
+                lockfile,
+            }));
+            if **current == new {
+                return false;
+            }
+            *current = Arc::new(new);
+            true
+        });
+        result.map(|update| {
+            update.unwrap_or(ConfigUpdate {
+                general: None,
+                priority: None,
+                lockfile: None,
+            })
+        })
+    }
+}
+
+pub(crate) fn load(path: &Path) -> Result<Config, ReloadError> {
+    Ok(Config::load(path)?)
+}
+
+/// Reads the plugin lockfile of the node, checking it locks exactly the plugins of the config.
+/// There needs to be none only without plugins
+pub(crate) fn read_lockfile(config: &Config) -> Result<Option<Lockfile>, ReloadError> {
+    let lockfile = match Lockfile::load(&config.node.lock_file) {
+        Ok(lockfile) => lockfile,
+        Err(LockfileError::Io(e))
+            if e.kind() == std::io::ErrorKind::NotFound && config.general.plugins.is_empty() =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    check_locked(&lockfile, &config.general.plugins)?;
+    Ok(Some(lockfile))
+}
+
+/// Appends the node to the nodes of the config file, leaving the rest of it as it was
+fn save_node(path: &Path, node: &Node) -> Result<(), ReloadError> {
+    let mut document: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let Some(nodes) = document
+        .pointer_mut("/general/nodes")
+        .and_then(Value::as_array_mut)
+    else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the config file has no general.nodes list",
+        )
+        .into());
+    };
+    nodes.push(serde_json::to_value(node)?);
+    Ok(config::write_file(path, &document)?)
+}
+
+/// Watches the config file, swapping the live config and telling the connected nodes when it changes.
+/// The returned watcher has to be kept alive for the reloading to continue
+pub fn watch_file<Ev>(
+    path: PathBuf,
+    config: LiveConfig,
+    node_manager: Arc<RwLock<NodeManager>>,
+    ev: Arc<Ev>,
+) -> notify::Result<RecommendedWatcher>
+where
+    Ev: AttemptConnectHandler + Send + Sync + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.send(res);
+    })?;
+    // Editors usually replace the file instead of writing to it, so watch the directory
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    info!("Watching {} for config changes", path.display());
+
+    tokio::spawn(async move {
+        while let Some(res) = rx.recv().await {
+            let event: notify::Event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("Error watching config file: {e}");
+                    continue;
+                }
+            };
+            if event.kind.is_access()
+                || !event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == path.file_name())
+            {
+                continue;
+            }
+            // A save usually comes as a burst of events, wait for it to settle
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            while rx.try_recv().is_ok() {}
+
+            let update = match load(&path).and_then(|new| config.reload(new)) {
+                Ok(update) => update,
+                Err(ReloadError::Invalid(diagnostics)) => {
+                    for diagnostic in diagnostics.errors() {
+                        error!("Config {diagnostic}");
+                    }
+                    error!("Not reloading invalid config");
+                    continue;
+                }
+                Err(e) => {
+                    error!("Not reloading config: {e}");
+                    continue;
+                }
+            };
+            if let Some(priority) = update.priority {
+                info!("Priority changed to {priority}");
+                NodeManager::broadcast(
+                    &node_manager,
+                    &ChatterMessage::NodeConfigUpdate { priority },
+                )
+                .await;
+            }
+            if let Some(diff) = update.general {
+                info!("General config reloaded");
+                NodeManager::broadcast(&node_manager, &ChatterMessage::GeneralConfigUpdate(diff))
+                    .await;
+                let current = config.get();
+                let names = current
+                    .general
+                    .nodes
+                    .iter()
+                    .map(|n| n.name.as_str())
+                    .filter(|&n| n != current.node.name)
+                    .collect::<Vec<_>>();
+                if ev.clone().attempt_connect(names).await.is_err() {
+                    error!("Error connecting to the nodes in the new config");
+                }
+            }
+            if let Some(lockfile) = update.lockfile {
+                match crate::install_plugins(&config.get(), &lockfile).await {
+                    Ok(()) => info!("Plugins installed from the new lockfile"),
+                    Err(e) => error!("Unable to install the plugins: {e}"),
+                }
+            }
+        }
+    });
+    Ok(watcher)
+}
@@ -1,23 +1,90 @@
 use std::{borrow::Cow, collections::HashMap, convert::Infallible, sync::Arc};
 
 use chatter_protocol::ChatterMessage;
-use config::{Config, Node};
-use futures_util::{stream::SplitSink, Sink, SinkExt, Stream, StreamExt};
-use pin_project::pin_project;
-use secure_comms::{connector, DataStream, DataStreamError, WebSocketByteStream};
+use config::{CompressionAlgorithm, Node, NodeConfig, WireCodec};
+use futures_util::StreamExt;
+use secure_comms::{
+    handshake_deadline, BoxedIo, Channel, ChannelReceiver, ChannelSender, Codec, Compression,
+    CompressionConfig, DataStream, DataStreamError, Frame, Multiplexer, Quic, StreamConfig, Tcp,
+    Transport, WebSocket,
+};
 use thiserror::Error;
-use tokio::{net::TcpStream, sync::RwLock, task::JoinHandle};
+use tokio::{sync::RwLock, task::JoinHandle};
 use tokio_rustls::rustls::ServerName;
-use tokio_tungstenite::MaybeTlsStream;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::live_config::{LiveConfig, ReloadError};
 
 use self::event_triggers::{EventHandlers, FromErrors};
 
 pub mod event_triggers;
 
+/// Compression and codecs this node offers to its peers
+pub fn stream_config(node: &NodeConfig) -> StreamConfig {
+    StreamConfig {
+        compression: CompressionConfig {
+            algorithms: node
+                .compression
+                .algorithms
+                .iter()
+                .map(|algorithm| match algorithm {
+                    CompressionAlgorithm::Zstd => Compression::Zstd,
+                    CompressionAlgorithm::Deflate => Compression::Deflate,
+                })
+                .collect(),
+            threshold: node.compression.threshold,
+        },
+        codecs: node
+            .codecs
+            .iter()
+            .map(|codec| match codec {
+                WireCodec::Bincode => Codec::Bincode,
+                WireCodec::Msgpack => Codec::MessagePack,
+                WireCodec::Cbor => Codec::Cbor,
+                WireCodec::Json => Codec::Json,
+            })
+            .collect(),
+        max_message_size: node.limits.max_message_size,
+        handshake_timeout: node.limits.handshake_timeout(),
+    }
+}
+
+/// The transports this node dials the others with
+struct Transports {
+    websocket: WebSocket,
+    tcp: Tcp,
+    quic: Quic,
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Self {
+            websocket: WebSocket {
+                path: "api/chatter".to_string(),
+            },
+            tcp: Tcp,
+            quic: Quic::default(),
+        }
+    }
+}
+
+impl Transports {
+    fn get(&self, transport: config::Transport) -> &dyn Transport {
+        match transport {
+            config::Transport::Quic => &self.quic,
+            config::Transport::Tls => &self.tcp,
+            config::Transport::Https | config::Transport::Http => &self.websocket,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct NodeManager {
     nodes: HashMap<Arc<str>, NodeStatus>,
+    /// URLs the nodes advertised when they were last connected
+    advertised: HashMap<Arc<str>, Url>,
+    transports: Arc<Transports>,
 }
 
 impl NodeManager {
@@ -28,7 +95,41 @@ impl NodeManager {
     }
 
     pub fn down<S: Into<Arc<str>>>(&mut self, key: S) {
-        self.nodes.insert(key.into(), NodeStatus::Down);
+        let key = key.into();
+        self.remember_advertised(&key);
+        self.nodes.insert(key, NodeStatus::Down);
+    }
+
+    /// Ends the connection to the node, if it is up, and marks it down
+    pub fn close<S: Into<Arc<str>>>(&mut self, key: S) {
+        let key = key.into();
+        if let Some(NodeStatus::Up(connection)) = self.nodes.get(&key) {
+            connection.handle.abort();
+        }
+        self.down(key);
+    }
+
+    fn remember_advertised(&mut self, key: &Arc<str>) {
+        if let Some(NodeStatus::Up(connection)) = self.nodes.get(key) {
+            if let Some(url) = connection
+                .state
+                .try_read()
+                .ok()
+                .and_then(|state| state.advertise.clone())
+            {
+                self.advertised.insert(key.clone(), url);
+            }
+        }
+    }
+
+    /// URL to dial the node at, the one it advertised or the one in the config
+    pub fn address(&mut self, node: &Node) -> Url {
+        let key = Arc::from(node.name.as_str());
+        self.remember_advertised(&key);
+        self.advertised
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| (*node.address).clone())
     }
 
     pub fn up<S: Into<Arc<str>>>(&mut self, key: S, connection: Connection) {
@@ -48,48 +149,108 @@ impl NodeManager {
             .map(|(x, y)| (&**x, y))
     }
 
+    /// Sends the message to every connected node. The lock is only held to get the connections,
+    /// as sending waits for the peers, which may be waiting for the lock themselves
+    pub async fn broadcast(node_manager: &RwLock<Self>, msg: &ChatterMessage) {
+        let connections = node_manager
+            .read()
+            .await
+            .nodes
+            .iter()
+            .filter_map(|(name, status)| match status {
+                NodeStatus::Up(connection) => Some((name.clone(), connection.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (name, connection) in connections {
+            if let Err(e) = connection.send(msg.clone()).await {
+                error!(name = &*name, "Error sending message: {e}");
+            }
+        }
+    }
+
+    /// Dials the node, with the transports its address lists, by preference.
+    /// The lock is only held to read the address and to mark the node up or down,
+    /// as dialing waits for the peer
     pub async fn connect<Ev>(
-        &mut self,
+        node_manager: &RwLock<Self>,
         node: &Node,
         client_config: Arc<tokio_rustls::rustls::ClientConfig>,
-        config: Arc<Config>,
+        config: LiveConfig,
         ev: Arc<Ev>,
     ) where
         Ev: EventHandlers + Send + Sync + 'static,
         ConnectionError: FromErrors<Ev>,
     {
-        let mut url = node.address.clone();
-        let scheme = if url.scheme() == "http" { "ws" } else { "wss" };
-        url.set_scheme(scheme).unwrap();
-
-        match tokio_tungstenite::connect_async(url.join("api/chatter").unwrap()).await {
-            Ok((ws, _)) => {
-                let connection = connector::<_, ChatterMessage, ChatterMessage>(
-                    ws,
-                    ServerName::try_from(node.name.as_str()).unwrap(),
-                    client_config.clone(),
-                )
-                .await
-                .unwrap();
-                info!("Connected to {} @ {}", node.name, node.address);
-                let connection =
-                    Connection::connected(connection, config.clone(), ev, node.name.clone());
-                let msg = ChatterMessage::Hello {
-                    config: config.general.clone(),
-                    priority: config.node.priority,
-                    connected: self.connected().map(|s| s.to_string()).collect(),
-                };
-                connection.send(msg).await.unwrap();
-                self.up(node.name.clone(), connection)
+        let (address, transports) = {
+            let mut node_manager = node_manager.write().await;
+            (node_manager.address(node), node_manager.transports.clone())
+        };
+        let down = move || async move { node_manager.write().await.down(node.name.clone()) };
+        let Ok(server_name) = ServerName::try_from(node.name.as_str()) else {
+            error!(
+                "Error connecting to {}: the name isn't a valid DNS name",
+                node.name
+            );
+            return down().await;
+        };
+        let address_transports = match config::transports(&address) {
+            Ok(transports) => transports,
+            Err(e) => {
+                error!("Error connecting to {} @ {}: {}", node.name, address, e);
+                return down().await;
+            }
+        };
+        let stream_config = stream_config(&config.get().node);
+        let mut io = None;
+        for transport in address_transports {
+            let url = transport.address(&address);
+            let connect =
+                transports
+                    .get(transport)
+                    .connect(&url, server_name.clone(), client_config.clone());
+            match handshake_deadline(stream_config.handshake_timeout, connect).await {
+                Ok(connected) => {
+                    io = Some((connected, url));
+                    break;
+                }
+                Err(e) => error!("Error connecting to {} @ {}: {}", node.name, url, e),
             }
+        }
+        let Some((io, url)) = io else {
+            return down().await;
+        };
+        let negotiate = DataStream::negotiate(io, &stream_config);
+        let connection = match handshake_deadline(stream_config.handshake_timeout, negotiate).await {
+            Ok(connection) => connection,
             Err(e) => {
-                error!(
-                    "Error connecting to {} @ {}: {}",
-                    node.name, node.address, e
-                );
-                self.down(node.name.clone())
+                error!("Error connecting to {} @ {}: {}", node.name, url, e);
+                return down().await;
             }
+        };
+        info!("Connected to {} @ {}", node.name, url);
+        let current = config.get();
+        let lock_hash = config.lock_hash();
+        let connection = Connection::new(connection, config, ev, node.name.clone());
+        let connected = node_manager
+            .read()
+            .await
+            .connected()
+            .map(|s| s.to_string())
+            .collect();
+        let msg = ChatterMessage::Hello {
+            config: current.general.clone(),
+            priority: current.node.priority,
+            connected,
+            lock_hash,
+            advertise: current.node.advertise.clone(),
+        };
+        if let Err(e) = connection.send(msg).await {
+            error!("Error connecting to {} @ {}: {}", node.name, url, e);
+            connection.handle.abort();
+            return down().await;
         }
+        node_manager.write().await.up(node.name.clone(), connection)
     }
 }
 
@@ -107,52 +268,39 @@ impl NodeStatus {
     }
 }
 
-type DataStreamShortServer<S, I, O = I> =
-    DataStream<tokio_rustls::server::TlsStream<WebSocketByteStream<S>>, I, O>;
-type DataStreamShortClient<S, I, O = I> =
-    DataStream<tokio_rustls::client::TlsStream<WebSocketByteStream<S>>, I, O>;
-type SplitSinkDataStreamServer<S, M> = SplitSink<DataStreamShortServer<S, M>, M>;
-type SplitSinkDataStreamClient<S, M> = SplitSink<DataStreamShortClient<S, M>, M>;
+/// Frames of the channels to a peer, over any transport
+pub type ChatterStream = DataStream<BoxedIo, Frame>;
 
 pub struct Connection<M = ChatterMessage> {
-    sink: Arc<RwLock<ConnectionSink<M>>>,
+    control: ChannelSender<M>,
+    task: ChannelSender<M>,
     handle: Arc<JoinHandle<Result<(), ConnectionError>>>,
     state: Arc<RwLock<ConnState>>,
 }
 
 struct ConnState {
     priority: u32,
+    queue_length: u32,
+    advertise: Option<Url>,
 }
 
 impl<M> Clone for Connection<M> {
     fn clone(&self) -> Self {
         Self {
-            sink: self.sink.clone(),
+            control: self.control.clone(),
+            task: self.task.clone(),
             handle: self.handle.clone(),
             state: self.state.clone(),
         }
     }
 }
 
-#[pin_project(project = ConnectionProj)]
-pub enum ConnectionSink<M> {
-    Accepted {
-        #[pin]
-        sink: SplitSinkDataStreamServer<axum::extract::ws::WebSocket, M>,
-    },
-    Connected {
-        #[pin]
-        sink: SplitSinkDataStreamClient<
-            tokio_tungstenite::WebSocketStream<MaybeTlsStream<TcpStream>>,
-            M,
-        >,
-    },
-}
-
 #[derive(Debug, Error)]
 pub enum ConnectionError {
     #[error("Configs dont match")]
     ConfigsDontMatch,
+    #[error("Plugin lockfiles dont match")]
+    LocksDontMatch,
     #[error(transparent)]
     DataStream(#[from] DataStreamError),
 }
@@ -164,82 +312,61 @@ impl From<Infallible> for ConnectionError {
 }
 
 impl Connection<ChatterMessage> {
-    pub fn accepted<Ev, Name>(
-        stream: DataStreamShortServer<axum::extract::ws::WebSocket, ChatterMessage>,
-        config: Arc<Config>,
-        ev: Arc<Ev>,
-        name: Name,
-    ) -> Self
-    where
-        Ev: EventHandlers + Send + Sync + 'static,
-        ConnectionError: FromErrors<Ev>,
-        Name: AsRef<str> + Send + Sync + 'static,
-    {
-        let (sink, stream) = stream.split();
-        let sink = Arc::new(RwLock::new(ConnectionSink::Accepted { sink }));
-        let state = Arc::new(RwLock::new(ConnState { priority: 0 }));
-        let handle = tokio::spawn(Self::receiver(
-            stream,
-            sink.clone(),
-            config,
-            ev,
-            state.clone(),
-            name,
-        ));
-        Self {
-            sink,
-            handle: Arc::new(handle),
-            state,
-        }
-    }
-
-    pub fn connected<Ev, Name>(
-        stream: DataStreamShortClient<
-            tokio_tungstenite::WebSocketStream<MaybeTlsStream<TcpStream>>,
-            ChatterMessage,
-        >,
-        config: Arc<Config>,
-        ev: Arc<Ev>,
-        name: Name,
-    ) -> Self
+    pub fn new<Ev, Name>(stream: ChatterStream, config: LiveConfig, ev: Arc<Ev>, name: Name) -> Self
     where
         Ev: EventHandlers + Send + Sync + 'static,
         ConnectionError: FromErrors<Ev>,
         Name: AsRef<str> + Send + Sync + 'static,
     {
-        let (sink, stream) = stream.split();
-        let sink = Arc::new(RwLock::new(ConnectionSink::Connected { sink }));
-        let state = Arc::new(RwLock::new(ConnState { priority: 0 }));
-        let handle = tokio::spawn(Self::receiver(
-            stream,
-            sink.clone(),
-            config,
-            ev,
-            state.clone(),
-            name,
-        ));
+        let mux = Multiplexer::new(stream);
+        let (control, control_stream) = mux
+            .channel(Channel::Control)
+            .expect("the channels of a new connection are free");
+        let (task, task_stream) = mux
+            .channel(Channel::Task)
+            .expect("the channels of a new connection are free");
+        let state = Arc::new(RwLock::new(ConnState {
+            priority: 0,
+            queue_length: 0,
+            advertise: None,
+        }));
+        let name: Arc<str> = Arc::from(name.as_ref());
+        let receivers = {
+            let (control, task) = (control.clone(), task.clone());
+            let state = state.clone();
+            // Each channel is read on its own, so a slow handler of one doesn't stop the other
+            async move {
+                tokio::select! {
+                    res = Self::receiver(
+                        control_stream,
+                        control,
+                        config.clone(),
+                        ev.clone(),
+                        state.clone(),
+                        name.clone(),
+                    ) => res,
+                    res = Self::receiver(task_stream, task, config, ev, state, name) => res,
+                }
+            }
+        };
         Self {
-            sink,
-            handle: Arc::new(handle),
+            control,
+            task,
+            handle: Arc::new(tokio::spawn(receivers)),
             state,
         }
     }
 
-    async fn receiver<
-        St: Stream<Item = Result<ChatterMessage, DataStreamError>> + Send + Unpin,
-        Si,
-        Ev,
-        Name,
-    >(
-        mut stream: St,
-        sink: Arc<RwLock<Si>>,
-        config: Arc<Config>,
+    /// Handles the messages of a channel, answering on the same channel
+    async fn receiver<Ev, Name>(
+        mut stream: ChannelReceiver<ChatterMessage>,
+        sender: ChannelSender<ChatterMessage>,
+        config: LiveConfig,
         ev: Arc<Ev>,
         state: Arc<RwLock<ConnState>>,
         name: Name,
     ) -> Result<(), ConnectionError>
     where
-        Si: Sink<ChatterMessage, Error = DataStreamError> + Unpin + Sync + Send,
         Ev: EventHandlers + Send + Sync,
         ConnectionError: FromErrors<Ev>,
         Name: AsRef<str> + Send + Sync,
@@ -248,11 +375,53 @@ impl Connection<ChatterMessage> {
             match stream.next().await {
                 None => break Ok(()),
                 Some(Ok(msg)) => match msg {
-                    ChatterMessage::QueueUpdate { length: _ } => todo!(),
-                    ChatterMessage::NodeConfigUpdate { priority: _ } => todo!(),
-                    ChatterMessage::GeneralConfigUpdate(_) => todo!(),
+                    ChatterMessage::QueueUpdate { length } => {
+                        debug!(name = name.as_ref(), length, "Queue update");
+                        state.write().await.queue_length = length;
+                    }
+                    ChatterMessage::NodeConfigUpdate { priority } => {
+                        debug!(name = name.as_ref(), priority, "Priority update");
+                        state.write().await.priority = priority;
+                    }
+                    ChatterMessage::GeneralConfigUpdate(diff) => {
+                        info!(name = name.as_ref(), "General config update");
+                        match config.apply_general_diff(&diff) {
+                            Ok(()) => {}
+                            Err(ReloadError::Invalid(diagnostics)) => {
+                                for diagnostic in diagnostics.errors() {
+                                    warn!(name = name.as_ref(), "Config update {diagnostic}");
+                                }
+                                warn!(name = name.as_ref(), "Ignored invalid config update");
+                            }
+                            Err(e) => warn!(name = name.as_ref(), "Ignored config update: {e}"),
+                        }
+                    }
+                    ChatterMessage::ScheduleFired { schedule, at } => {
+                        debug!(name = name.as_ref(), schedule, at, "Schedule fired");
+                        ev.clone().schedule_fired(schedule, at).await?;
+                    }
+                    ChatterMessage::SendTask { id, task, params } => {
+                        debug!(name = name.as_ref(), id, task, "Job received");
+                        let error = ev
+                            .clone()
+                            .job_received(id.clone(), task, params)
+                            .await?
+                            .err()
+                            .map(|e| e.to_string());
+                        // The peer reads the answers right away, so there is room unless it misbehaves
+                        if let Err(e) = sender.try_send(ChatterMessage::TaskAccepted { id, error }) {
+                            warn!(name = name.as_ref(), "Answer to a job not sent: {e}");
+                        }
+                    }
+                    ChatterMessage::TaskAccepted { id, error } => {
+                        ev.clone().job_accepted(id, error).await?;
+                    }
                     ChatterMessage::Ping(x) => {
-                        let _ = sink.write().await.send(ChatterMessage::Pong(x)).await;
+                        // Waiting for room could stop this channel for good if the peer
+                        // is waiting too, and a missing pong only makes it ping again
+                        if let Err(e) = sender.try_send(ChatterMessage::Pong(x)) {
+                            debug!(name = name.as_ref(), "Pong not sent: {e}");
+                        }
                     }
                     ChatterMessage::Pong(x) => {
                         ev.clone().pong(x).await?;
@@ -261,15 +430,24 @@ impl Connection<ChatterMessage> {
                         config: c,
                         priority,
                         connected,
+                        lock_hash,
+                        advertise,
                     } => {
                         debug!(name = name.as_ref(), "Hello");
                         // dbg!(&c);
                         // dbg!(&config.general);
                         // dbg!(c == config.general);
-                        if c != config.general {
+                        if c != config.get().general {
                             break Err(ConnectionError::ConfigsDontMatch);
                         }
-                        state.write().await.priority = priority;
+                        if lock_hash != config.lock_hash() {
+                            break Err(ConnectionError::LocksDontMatch);
+                        }
+                        {
+                            let mut state = state.write().await;
+                            state.priority = priority;
+                            state.advertise = advertise;
+                        }
                         ev.clone()
                             .attempt_connect(connected.iter().map(String::as_str))
                             .await?;
@@ -284,51 +462,25 @@ impl Connection<ChatterMessage> {
         res
     }
 
+    /// Sends the message on its channel, the task one for jobs and queue lengths
+    /// and the control one for the rest
     pub async fn send(&self, msg: ChatterMessage) -> Result<(), DataStreamError> {
-        self.sink.write().await.send(msg).await
-    }
-}
-
-impl<M> Sink<M> for ConnectionSink<M>
-where
-    M: serde::Serialize,
-{
-    type Error = DataStreamError;
-
-    fn poll_ready(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        match self.project() {
-            ConnectionProj::Accepted { sink, .. } => sink.poll_ready(cx),
-            ConnectionProj::Connected { sink, .. } => sink.poll_ready(cx),
+        match msg {
+            ChatterMessage::SendTask { .. }
+            | ChatterMessage::TaskAccepted { .. }
+            | ChatterMessage::QueueUpdate { .. } => self.task.send(msg).await,
+            _ => self.control.send(msg).await,
         }
     }
 
-    fn start_send(self: std::pin::Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
-        match self.project() {
-            ConnectionProj::Accepted { sink, .. } => sink.start_send(item),
-            ConnectionProj::Connected { sink, .. } => sink.start_send(item),
-        }
+    pub async fn priority(&self) -> u32 {
+        self.state.read().await.priority
     }
 
-    fn poll_flush(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        match self.project() {
-            ConnectionProj::Accepted { sink, .. } => sink.poll_flush(cx),
-            ConnectionProj::Connected { sink, .. } => sink.poll_flush(cx),
-        }
+    /// Jobs the node last said it has running or waiting
+    pub async fn queue_length(&self) -> usize {
+        self.state.read().await.queue_length as usize
     }
 
-    fn poll_close(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        match self.project() {
-            ConnectionProj::Accepted { sink, .. } => sink.poll_close(cx),
-            ConnectionProj::Connected { sink, .. } => sink.poll_close(cx),
-        }
-    }
 }
+
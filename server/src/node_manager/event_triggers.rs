@@ -1,9 +1,16 @@
 use std::{collections::HashSet, convert::Infallible, sync::Arc};
 
 use async_trait::async_trait;
-use config::Config;
+use serde_json::Value;
 use tokio::sync::RwLock;
-use tokio_rustls::rustls::ClientConfig;
+use tracing::error;
+
+use crate::{
+    jobs::{AcceptError, Jobs},
+    live_config::LiveConfig,
+    scheduler::Scheduler,
+    tls::LiveTls,
+};
 
 use super::NodeManager;
 
@@ -22,11 +29,44 @@ pub trait PongHandler {
     async fn pong(self: Arc<Self>, id: u32) -> Result<(), Self::Error>;
 }
 
-pub trait EventHandlers: AttemptConnectHandler + PongHandler {}
-impl<T> EventHandlers for T where T: AttemptConnectHandler + PongHandler {}
+#[async_trait]
+pub trait ScheduleFiredHandler {
+    type Error;
+    async fn schedule_fired(self: Arc<Self>, schedule: String, at: i64) -> Result<(), Self::Error>;
+}
+
+#[async_trait]
+pub trait JobReceivedHandler {
+    type Error;
+    /// Takes a job a peer placed on this node, or tells why it can't
+    async fn job_received(
+        self: Arc<Self>,
+        id: String,
+        task: String,
+        params: Vec<Value>,
+    ) -> Result<Result<(), AcceptError>, Self::Error>;
+    /// The peer a job was placed on took it, or refused it with the error
+    async fn job_accepted(
+        self: Arc<Self>,
+        id: String,
+        error: Option<String>,
+    ) -> Result<(), Self::Error>;
+}
+
+pub trait EventHandlers:
+    AttemptConnectHandler + PongHandler + ScheduleFiredHandler + JobReceivedHandler
+{
+}
+impl<T> EventHandlers for T where
+    T: AttemptConnectHandler + PongHandler + ScheduleFiredHandler + JobReceivedHandler
+{
+}
 
 pub trait FromErrors<Ev>:
-    From<<Ev as PongHandler>::Error> + From<<Ev as AttemptConnectHandler>::Error>
+    From<<Ev as PongHandler>::Error>
+    + From<<Ev as AttemptConnectHandler>::Error>
+    + From<<Ev as ScheduleFiredHandler>::Error>
+    + From<<Ev as JobReceivedHandler>::Error>
 where
     Ev: EventHandlers,
 {
@@ -35,7 +75,10 @@ where
 impl<T, Ev> FromErrors<Ev> for T
 where
     Ev: EventHandlers,
-    T: From<<Ev as PongHandler>::Error> + From<<Ev as AttemptConnectHandler>::Error>,
+    T: From<<Ev as PongHandler>::Error>
+        + From<<Ev as AttemptConnectHandler>::Error>
+        + From<<Ev as ScheduleFiredHandler>::Error>
+        + From<<Ev as JobReceivedHandler>::Error>,
 {
 }
 
@@ -63,23 +106,60 @@ impl PongHandler for MockEv {
     }
 }
 
+#[async_trait]
+impl ScheduleFiredHandler for MockEv {
+    type Error = Infallible;
+
+    async fn schedule_fired(self: Arc<Self>, _: String, _: i64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobReceivedHandler for MockEv {
+    type Error = Infallible;
+
+    async fn job_received(
+        self: Arc<Self>,
+        _: String,
+        _: String,
+        _: Vec<Value>,
+    ) -> Result<Result<(), AcceptError>, Self::Error> {
+        Ok(Ok(()))
+    }
+
+    async fn job_accepted(
+        self: Arc<Self>,
+        _: String,
+        _: Option<String>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct EventHandlersImpl {
-    config: Arc<Config>,
-    client_config: Arc<ClientConfig>,
+    config: LiveConfig,
+    tls: LiveTls,
     node_manager: Arc<RwLock<NodeManager>>,
+    scheduler: Arc<Scheduler>,
+    jobs: Arc<Jobs>,
 }
 
 impl EventHandlersImpl {
     pub fn new(
-        config: Arc<Config>,
-        client_config: Arc<ClientConfig>,
+        config: LiveConfig,
+        tls: LiveTls,
         node_manager: Arc<RwLock<NodeManager>>,
+        scheduler: Arc<Scheduler>,
+        jobs: Arc<Jobs>,
     ) -> Self {
         Self {
             config,
-            client_config,
+            tls,
             node_manager,
+            scheduler,
+            jobs,
         }
     }
 }
@@ -93,25 +173,25 @@ impl AttemptConnectHandler for EventHandlersImpl {
         names: I,
     ) -> Result<(), Self::Error> {
         let names = names.into_iter().collect::<HashSet<_>>();
+        let config = self.config.get();
         let nodes = {
             let read = self.node_manager.read().await;
-            self.config
+            config
                 .general
                 .nodes
                 .iter()
                 .filter(|n| names.contains(n.name.as_str()) && !read.get(&n.name).is_up())
                 .collect::<Vec<_>>()
         };
-        let mut write = self.node_manager.write().await;
         for node in nodes {
-            write
-                .connect(
-                    node,
-                    self.client_config.clone(),
-                    self.config.clone(),
-                    self.clone(),
-                )
-                .await
+            NodeManager::connect(
+                &self.node_manager,
+                node,
+                self.tls.client(),
+                self.config.clone(),
+                self.clone(),
+            )
+            .await
         }
         Ok(())
     }
@@ -126,3 +206,40 @@ impl PongHandler for EventHandlersImpl {
         Ok(())
     }
 }
+
+#[async_trait]
+impl ScheduleFiredHandler for EventHandlersImpl {
+    type Error = Infallible;
+
+    async fn schedule_fired(self: Arc<Self>, schedule: String, at: i64) -> Result<(), Self::Error> {
+        self.scheduler.fired(schedule, at).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl JobReceivedHandler for EventHandlersImpl {
+    type Error = Infallible;
+
+    async fn job_received(
+        self: Arc<Self>,
+        id: String,
+        task: String,
+        params: Vec<Value>,
+    ) -> Result<Result<(), AcceptError>, Self::Error> {
+        let res = self.jobs.accept(id.clone(), task, params);
+        if let Err(e) = &res {
+            error!(job = id, "Refused a job: {e}");
+        }
+        Ok(res)
+    }
+
+    async fn job_accepted(
+        self: Arc<Self>,
+        id: String,
+        error: Option<String>,
+    ) -> Result<(), Self::Error> {
+        self.jobs.accepted(&id, error);
+        Ok(())
+    }
+}
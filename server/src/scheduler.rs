@@ -0,0 +1,269 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chatter_protocol::ChatterMessage;
+use chrono::{DateTime, Utc};
+use config::{Config, Schedule};
+use tokio::{sync::RwLock, time::MissedTickBehavior};
+use tracing::{error, info, warn};
+
+use crate::{
+    jobs::{self, Jobs},
+    live_config::LiveConfig,
+    node_manager::{NodeManager, NodeStatus},
+};
+
+/// Fires the scheduled jobs this node is responsible for.
+///
+/// Every schedule is owned by a single node, chosen by rendezvous hashing among the nodes that are up
+/// and allowed to run its task, so ownership only moves when the owner comes or goes.
+/// Nothing is fired without a majority of the nodes of the config up, so the two sides of a partition
+/// can't both fire, and ownership only moves once the nodes up have stayed the same for a check.
+/// Fired occurrences are told to the rest of the cluster and kept on disk, so whoever takes over,
+/// or the whole cluster after a restart, knows what was missed.
+pub struct Scheduler {
+    config: LiveConfig,
+    node_manager: Arc<RwLock<NodeManager>>,
+    jobs: Arc<Jobs>,
+    /// Last occurrence of each schedule fired by any node
+    fired: RwLock<HashMap<String, DateTime<Utc>>>,
+    /// Where `fired` is kept
+    file: PathBuf,
+}
+
+impl Scheduler {
+    pub fn new(
+        config: LiveConfig,
+        node_manager: Arc<RwLock<NodeManager>>,
+        jobs: Arc<Jobs>,
+    ) -> Self {
+        let file = config.get().node.schedules_file.clone();
+        Self {
+            config,
+            node_manager,
+            jobs,
+            fired: RwLock::new(load(&file)),
+            file,
+        }
+    }
+
+    /// Records an occurrence fired by another node
+    pub async fn fired(&self, schedule: String, at: i64) {
+        let Some(at) = DateTime::from_timestamp(at, 0) else {
+            return;
+        };
+        let mut fired = self.fired.write().await;
+        if fired.get(&schedule).is_some_and(|&last| last >= at) {
+            return;
+        }
+        fired.insert(schedule, at);
+        self.save(&fired).await;
+    }
+
+    /// Writes the fired occurrences to disk, with the lock held so the writes stay in order
+    async fn save(&self, fired: &HashMap<String, DateTime<Utc>>) {
+        let document = fired
+            .iter()
+            .map(|(name, at)| (name.clone(), at.timestamp().into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+        let path = self.file.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            config::write_file(&path, &document)
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|res| res);
+        if let Err(e) = res {
+            error!("Unable to write {}: {e}", self.file.display());
+        }
+    }
+
+    /// Tells a node that just came up what was fired, in case it missed it while away
+    async fn sync(&self, name: &str) {
+        let NodeStatus::Up(connection) = self.node_manager.read().await.get(name).into_owned()
+        else {
+            return;
+        };
+        let fired = self.fired.read().await.clone();
+        for (schedule, at) in fired {
+            let msg = ChatterMessage::ScheduleFired {
+                schedule,
+                at: at.timestamp(),
+            };
+            if let Err(e) = connection.send(msg).await {
+                error!(name, "Error sending fired schedules: {e}");
+                return;
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut last_check = Utc::now();
+        let mut owned = HashSet::new();
+        let mut last_up = HashSet::new();
+        let mut had_quorum = true;
+        loop {
+            interval.tick().await;
+            let now = Utc::now();
+            let config = self.config.get();
+            let up = {
+                let read = self.node_manager.read().await;
+                read.connected()
+                    .map(|name| name.to_string())
+                    .chain([config.node.name.clone()])
+                    .collect::<HashSet<_>>()
+            };
+            for name in up.difference(&last_up) {
+                if *name != config.node.name {
+                    self.sync(name).await;
+                }
+            }
+            let quorum = has_quorum(&config, &up);
+            if quorum != had_quorum {
+                if quorum {
+                    info!("A majority of the nodes is up, firing schedules again");
+                } else {
+                    warn!("Less than a majority of the nodes is up, not firing schedules");
+                }
+                had_quorum = quorum;
+            }
+            let settled = up == last_up;
+            let mut now_owned = HashSet::new();
+            for (name, schedule) in &config.general.schedules {
+                let owns = if settled {
+                    owner(&config, name, schedule, &up) == Some(config.node.name.as_str())
+                } else {
+                    // Keep what was owned until the peers that just came had the time to sync
+                    owned.contains(name)
+                };
+                if !quorum || !owns {
+                    continue;
+                }
+                now_owned.insert(name.clone());
+                let mut due = Vec::new();
+                if !owned.contains(name) {
+                    // Just took it over, catch up with what the previous owner didn't fire
+                    if let Some(&last) = self.fired.read().await.get(name) {
+                        match schedule.missed_runs(last, last_check) {
+                            Ok(missed) => due.extend(missed),
+                            Err(e) => error!(schedule = name, "Invalid schedule: {e}"),
+                        }
+                    }
+                }
+                match schedule.occurrences(last_check, now) {
+                    Ok(occurrences) => due.extend(occurrences),
+                    Err(e) => error!(schedule = name, "Invalid schedule: {e}"),
+                }
+                for at in due {
+                    self.fire(&config, name, schedule, at).await;
+                }
+            }
+            owned = now_owned;
+            last_up = up;
+            last_check = now;
+        }
+    }
+
+    async fn fire(&self, config: &Config, name: &str, schedule: &Schedule, at: DateTime<Utc>) {
+        {
+            let mut fired = self.fired.write().await;
+            if fired.get(name).is_some_and(|&last| last >= at) {
+                return;
+            }
+            fired.insert(name.to_string(), at);
+            self.save(&fired).await;
+        }
+        let msg = ChatterMessage::ScheduleFired {
+            schedule: name.to_string(),
+            at: at.timestamp(),
+        };
+        NodeManager::broadcast(&self.node_manager, &msg).await;
+
+        let Some(task) = config.general.tasks.get(&schedule.task).cloned() else {
+            error!(schedule = name, "Unknown task {}", schedule.task);
+            return;
+        };
+        let params = match task.resolve_params(schedule.params.clone()) {
+            Ok(params) => params,
+            Err(e) => {
+                error!(schedule = name, "Invalid params: {e}");
+                return;
+            }
+        };
+        let id = jobs::new_id();
+        info!(schedule = name, job = id, "Firing {} for {at}", schedule.task);
+        self.jobs.run(id, schedule.task.clone(), task, params);
+    }
+}
+
+/// Node in charge of firing the schedule, if any of the nodes that can run its task is up.
+/// Coordinator eligible nodes are picked before plain workers.
+fn owner<'a>(
+    config: &'a Config,
+    name: &str,
+    schedule: &Schedule,
+    up: &HashSet<String>,
+) -> Option<&'a str> {
+    let task = config.general.tasks.get(&schedule.task)?;
+    config
+        .general
+        .nodes
+        .iter()
+        .filter(|node| up.contains(&node.name) && task.can_run_on_node(node))
+        .max_by_key(|node| {
+            (
+                node.role.can_coordinate(),
+                weight(name, &node.name),
+                node.name.as_str(),
+            )
+        })
+        .map(|node| node.name.as_str())
+}
+
+/// Whether more than half of the nodes of the config are up
+fn has_quorum(config: &Config, up: &HashSet<String>) -> bool {
+    let nodes = &config.general.nodes;
+    nodes.iter().filter(|node| up.contains(&node.name)).count() * 2 > nodes.len()
+}
+
+/// Last fired occurrences kept in the file, none if it can't be read
+fn load(path: &Path) -> HashMap<String, DateTime<Utc>> {
+    let document = match std::fs::read_to_string(path) {
+        Ok(document) => document,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => {
+            error!("Unable to read {}: {e}", path.display());
+            return HashMap::new();
+        }
+    };
+    match serde_json::from_str::<HashMap<String, i64>>(&document) {
+        Ok(fired) => fired
+            .into_iter()
+            .filter_map(|(name, at)| Some((name, DateTime::from_timestamp(at, 0)?)))
+            .collect(),
+        Err(e) => {
+            error!("Unable to read {}: {e}", path.display());
+            HashMap::new()
+        }
+    }
+}
+
+/// FNV-1a, as it has to give the same result in every node
+fn weight(schedule: &str, node: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64;
+    for byte in schedule.bytes().chain([0]).chain(node.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
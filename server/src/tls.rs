@@ -0,0 +1,274 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use config::Config;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tracing::{error, info, warn};
+
+use crate::{
+    cache::{CacheError, CertificatesCache},
+    live_config::LiveConfig,
+    node_manager::NodeManager,
+    verify::{
+        warning, CertInfo, ClientVerifier, Identities, PeerCerts, Revocations, ServerVerifier,
+    },
+};
+
+fn server_config(
+    conf: &Config,
+    cache: &CertificatesCache,
+    revocations: Arc<Revocations>,
+    identities: Arc<Identities>,
+) -> Result<Arc<ServerConfig>, CacheError> {
+    let mut rcs = RootCertStore::empty();
+    for cert in cache.get_ca(conf)? {
+        rcs.add(cert)?;
+    }
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(ClientVerifier::new(
+            rcs,
+            revocations,
+            identities,
+        )))
+        .with_single_cert(cache.get_cert(conf)?, cache.get_key(conf)?)?;
+    Ok(Arc::new(config))
+}
+
+fn client_config(
+    conf: &Config,
+    cache: &CertificatesCache,
+    revocations: Arc<Revocations>,
+    identities: Arc<Identities>,
+) -> Result<Arc<ClientConfig>, CacheError> {
+    let mut rcs = RootCertStore::empty();
+    for cert in cache.get_ca(conf)? {
+        rcs.add(cert)?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(ServerVerifier::new(
+            rcs,
+            revocations,
+            identities,
+        )))
+        .with_client_auth_cert(cache.get_cert(conf)?, cache.get_key(conf)?)?;
+    Ok(Arc::new(config))
+}
+
+#[derive(Clone)]
+pub(crate) struct TlsConfigs {
+    pub(crate) server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+    own: Option<CertInfo>,
+    revocations: Arc<Revocations>,
+    identities: Arc<Identities>,
+}
+
+impl TlsConfigs {
+    fn load(conf: &Config, peers: Arc<PeerCerts>) -> Result<Self, CacheError> {
+        let cache = CertificatesCache::default();
+        let revocations = Arc::new(Revocations::load(conf, peers)?);
+        let identities = Arc::new(Identities::new(&conf.general));
+        let own = cache
+            .get_cert(conf)?
+            .first()
+            .and_then(|cert| CertInfo::parse(&cert.0));
+        if let Some(own) = own.as_ref().filter(|own| own.expires_within(warning(conf))) {
+            warn!("The certificate of this node expires at {}", own.not_after);
+        }
+        Ok(Self {
+            server: server_config(conf, &cache, revocations.clone(), identities.clone())?,
+            client: client_config(conf, &cache, revocations.clone(), identities.clone())?,
+            own,
+            revocations,
+            identities,
+        })
+    }
+}
+
+/// TLS configs of the node, rebuilt when the certificates change.
+/// New handshakes use the new configs, and established connections are checked against them
+#[derive(Clone)]
+pub struct LiveTls {
+    tx: Arc<watch::Sender<TlsConfigs>>,
+    peers: Arc<PeerCerts>,
+}
+
+impl LiveTls {
+    pub fn load(conf: &Config) -> Result<Self, CacheError> {
+        let peers = Arc::new(PeerCerts::default());
+        Ok(Self {
+            tx: Arc::new(watch::channel(TlsConfigs::load(conf, peers.clone())?).0),
+            peers,
+        })
+    }
+
+    pub fn server(&self) -> Arc<ServerConfig> {
+        self.tx.borrow().server.clone()
+    }
+
+    /// Notified of every reload, for listeners that keep a server config of their own
+    pub(crate) fn subscribe(&self) -> watch::Receiver<TlsConfigs> {
+        self.tx.subscribe()
+    }
+
+    pub fn client(&self) -> Arc<ClientConfig> {
+        self.tx.borrow().client.clone()
+    }
+
+    /// Certificate of this node
+    pub fn own_cert(&self) -> Option<CertInfo> {
+        self.tx.borrow().own.clone()
+    }
+
+    /// Node the handshake identified the peer with this certificate as
+    pub fn identity(&self, certificate: &[u8]) -> Option<String> {
+        self.peers.node(certificate)
+    }
+
+    /// Certificates of the peers seen in handshakes
+    pub fn peer_certs(&self) -> Vec<CertInfo> {
+        self.peers.all()
+    }
+
+    /// Reads the certificates and revocation lists again, keeping the current configs if they are invalid.
+    /// Returns the connected peers whose certificate isn't accepted anymore
+    pub fn reload(&self, conf: &Config) -> Result<Vec<String>, CacheError> {
+        let configs = TlsConfigs::load(conf, self.peers.clone())?;
+        let rejected = self.peers.reject(&configs.revocations, &configs.identities);
+        self.tx.send_replace(configs);
+        Ok(rejected)
+    }
+}
+
+/// Whether the change of config affects the TLS configs
+fn tls_changed(old: &Config, new: &Config) -> bool {
+    let identities = |config: &Config| {
+        config
+            .general
+            .nodes
+            .iter()
+            .map(|node| (node.name.clone(), node.fingerprints.clone()))
+            .collect::<Vec<_>>()
+    };
+    old.node.crl_files != new.node.crl_files
+        || identities(old) != identities(new)
+        || old.node.cert_expiry_warning_days != new.node.cert_expiry_warning_days
+        || old.general.denied_certificates != new.general.denied_certificates
+}
+
+/// Warns every hour about the certificates close to expiring
+pub async fn monitor_expiry(tls: LiveTls, config: LiveConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let warning = warning(&config.get());
+        for cert in tls.own_cert().into_iter().chain(tls.peer_certs()) {
+            if cert.expires_within(warning) {
+                warn!("The certificate of {} expires at {}", cert.name, cert.not_after);
+            }
+        }
+    }
+}
+
+/// Closes the connections of the peers whose certificate isn't accepted anymore
+async fn disconnect(node_manager: &RwLock<NodeManager>, rejected: Vec<String>) {
+    if rejected.is_empty() {
+        return;
+    }
+    let mut node_manager = node_manager.write().await;
+    for name in rejected {
+        warn!("Disconnecting {name}, its certificate isn't accepted anymore");
+        node_manager.close(name);
+    }
+}
+
+/// Watches the CA, certificate, key and revocation files, and the config,
+/// reloading the TLS configs when they change.
+/// The returned watcher has to be kept alive for the reloading to continue
+pub fn watch_files(
+    tls: LiveTls,
+    config: LiveConfig,
+    node_manager: Arc<RwLock<NodeManager>>,
+) -> notify::Result<RecommendedWatcher> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.send(res);
+    })?;
+    let current = config.get();
+    let files = [
+        &current.node.ca_file,
+        &current.node.cert_file,
+        &current.node.key_file,
+    ]
+    .into_iter()
+    .chain(&current.node.crl_files)
+    .map(|file| std::fs::canonicalize(file).unwrap_or_else(|_| file.clone()))
+    .collect::<HashSet<_>>();
+    // Certificates are usually replaced instead of written to, so watch the directories
+    let dirs = files
+        .iter()
+        .map(|file| match file.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect::<HashSet<_>>();
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    info!("Watching the certificates for changes");
+
+    let mut changes = config.subscribe();
+    let reload_tls = tls.clone();
+    let reload_node_manager = node_manager.clone();
+    tokio::spawn(async move {
+        let mut old = changes.borrow_and_update().clone();
+        while changes.changed().await.is_ok() {
+            let new = changes.borrow_and_update().clone();
+            if tls_changed(&old, &new) {
+                match reload_tls.reload(&new) {
+                    Ok(rejected) => {
+                        info!("Certificate checks reloaded");
+                        disconnect(&reload_node_manager, rejected).await;
+                    }
+                    Err(e) => error!("Not reloading the certificate checks: {e}"),
+                }
+            }
+            old = new;
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(res) = rx.recv().await {
+            let event: notify::Event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("Error watching the certificates: {e}");
+                    continue;
+                }
+            };
+            if event.kind.is_access() || !event.paths.iter().any(|p| files.contains(p)) {
+                continue;
+            }
+            // The key and certificate are usually written one after the other
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            while rx.try_recv().is_ok() {}
+
+            match tls.reload(&config.get()) {
+                Ok(rejected) => {
+                    info!("Certificates reloaded");
+                    disconnect(&node_manager, rejected).await;
+                }
+                Err(e) => error!("Not reloading the certificates: {e}"),
+            }
+        }
+    });
+    Ok(watcher)
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use config::{normalize_fingerprint, Config, GeneralConfig};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier},
+    Certificate, CertificateError, DistinguishedName, Error, RootCertStore, ServerName,
+};
+use secure_comms::peer_names;
+use source_cache::sha256_hex;
+use thiserror::Error;
+use tracing::warn;
+use x509_parser::{
+    certificate::X509Certificate, extensions::GeneralName, parse_x509_certificate,
+    parse_x509_crl, pem::Pem,
+};
+
+use crate::cache::{load_certs, CacheError};
+
+/// What is known of a certificate for reporting its expiry
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CertInfo {
+    pub name: String,
+    pub fingerprint: String,
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertInfo {
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = parse_x509_certificate(der).ok()?;
+        Some(Self {
+            name: cert_name(&cert),
+            fingerprint: sha256_hex(der),
+            not_after: DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)?,
+        })
+    }
+
+    pub fn expires_within(&self, warning: Duration) -> bool {
+        self.not_after - Utc::now() < warning
+    }
+}
+
+/// First DNS name of the certificate, which is the node name, or its common name
+fn cert_name(cert: &X509Certificate) -> String {
+    cert.subject_alternative_name()
+        .ok()
+        .flatten()
+        .and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(name) => Some((*name).to_string()),
+                _ => None,
+            })
+        })
+        .or_else(|| {
+            cert.subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| cert.subject().to_string())
+}
+
+/// Certificates of the peers, by name, as seen in their last handshake
+#[derive(Default)]
+pub struct PeerCerts(Mutex<HashMap<String, (CertInfo, Certificate)>>);
+
+impl PeerCerts {
+    pub fn all(&self) -> Vec<CertInfo> {
+        let mut certs = self
+            .0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect::<Vec<_>>();
+        certs.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        certs
+    }
+
+    /// Node the verifiers identified the certificate as
+    pub fn node(&self, der: &[u8]) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, (_, cert))| cert.0 == der)
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Records the certificate, returning whether it is new for its peer
+    fn insert(&self, info: CertInfo, cert: &Certificate) -> bool {
+        let mut certs = self.0.lock().unwrap();
+        let new = certs
+            .get(&info.name)
+            .is_none_or(|(seen, _)| seen.fingerprint != info.fingerprint);
+        certs.insert(info.name.clone(), (info, cert.clone()));
+        new
+    }
+
+    /// Forgets the peers whose certificate the checks now reject, returning their names
+    pub fn reject(&self, revocations: &Revocations, identities: &Identities) -> Vec<String> {
+        let mut rejected = Vec::new();
+        self.0.lock().unwrap().retain(|name, (info, cert)| {
+            let accepted = revocations.check(cert).is_ok()
+                && identities.check_pinned(name, &info.fingerprint).is_ok();
+            if !accepted {
+                rejected.push(name.clone());
+            }
+            accepted
+        });
+        rejected.sort_unstable();
+        rejected
+    }
+}
+
+/// Certificates rejected on top of the usual chain validation
+pub struct Revocations {
+    denied: HashSet<String>,
+    /// Issuer and serial number, both DER encoded
+    revoked: HashSet<(Vec<u8>, Vec<u8>)>,
+    warning: Duration,
+    peers: Arc<PeerCerts>,
+}
+
+impl Revocations {
+    /// Loads the configured revocation lists, which must be signed by the CA.
+    /// An outdated list is still applied, but reported
+    pub fn load(conf: &Config, peers: Arc<PeerCerts>) -> Result<Self, CacheError> {
+        let mut revoked = HashSet::new();
+        let ca = if conf.node.crl_files.is_empty() {
+            Vec::new()
+        } else {
+            load_certs(&conf.node.ca_file)?
+        };
+        let ca = ca
+            .iter()
+            .filter_map(|cert| parse_x509_certificate(&cert.0).ok())
+            .map(|(_, cert)| cert)
+            .collect::<Vec<_>>();
+        for file in &conf.node.crl_files {
+            let invalid = || CacheError::InvalidCrl(file.clone());
+            let data = std::fs::read(file)?;
+            let ders = if data.starts_with(b"-----") {
+                Pem::iter_from_buffer(&data)
+                    .map(|pem| pem.map(|pem| pem.contents))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid())?
+            } else {
+                vec![data]
+            };
+            for der in ders {
+                let (_, crl) = parse_x509_crl(&der).map_err(|_| invalid())?;
+                let signed = ca.iter().any(|ca| {
+                    ca.subject().as_raw() == crl.issuer().as_raw()
+                        && crl.verify_signature(ca.public_key()).is_ok()
+                });
+                if !signed {
+                    return Err(CacheError::UntrustedCrl(file.clone()));
+                }
+                if let Some(next_update) = crl.next_update() {
+                    if next_update.timestamp() < Utc::now().timestamp() {
+                        warn!(
+                            "The certificate revocation list {} was due to be updated at {}",
+                            file.display(),
+                            next_update
+                        );
+                    }
+                }
+                let issuer = crl.issuer().as_raw().to_vec();
+                revoked.extend(
+                    crl.iter_revoked_certificates()
+                        .map(|cert| (issuer.clone(), cert.raw_serial().to_vec())),
+                );
+            }
+        }
+        Ok(Self {
+            denied: conf
+                .general
+                .denied_certificates
+                .iter()
+                .map(|f| normalize_fingerprint(f))
+                .collect(),
+            revoked,
+            warning: warning(conf),
+            peers,
+        })
+    }
+
+    /// Records the certificate of a node as seen in a handshake.
+    /// A certificate close to expiring is reported the first time it is seen
+    pub fn seen(&self, node: &str, end_entity: &Certificate) {
+        if let Some(mut info) = CertInfo::parse(&end_entity.0) {
+            info.name = node.to_string();
+            let (expiring, not_after) = (info.expires_within(self.warning), info.not_after);
+            if self.peers.insert(info, end_entity) && expiring {
+                warn!("The certificate of {node} expires at {not_after}");
+            }
+        }
+    }
+
+    /// Rejects denied and revoked certificates
+    pub fn check(&self, end_entity: &Certificate) -> Result<(), Error> {
+        let revoked = Err(Error::InvalidCertificate(CertificateError::Revoked));
+        if self.denied.contains(&sha256_hex(&end_entity.0)) {
+            return revoked;
+        }
+        let (_, cert) = parse_x509_certificate(&end_entity.0)
+            .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let key = (cert.issuer().as_raw().to_vec(), cert.raw_serial().to_vec());
+        if self.revoked.contains(&key) {
+            return revoked;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("the certificate names no configured node: {0:?}")]
+    Unknown(Vec<String>),
+    #[error("the certificate names several configured nodes: {0:?}")]
+    Ambiguous(Vec<String>),
+    #[error("the certificate of {0} doesn't match its pinned fingerprints")]
+    NotPinned(String),
+}
+
+impl From<IdentityError> for Error {
+    fn from(e: IdentityError) -> Self {
+        Self::InvalidCertificate(CertificateError::Other(Arc::new(e)))
+    }
+}
+
+/// Nodes of the config a certificate may belong to, with their pinned fingerprints
+pub struct Identities(HashMap<String, HashSet<String>>);
+
+impl Identities {
+    pub fn new(general: &GeneralConfig) -> Self {
+        Self(
+            general
+                .nodes
+                .iter()
+                .map(|node| {
+                    let pinned = node
+                        .fingerprints
+                        .iter()
+                        .map(|f| normalize_fingerprint(f))
+                        .collect();
+                    (node.name.clone(), pinned)
+                })
+                .collect(),
+        )
+    }
+
+    /// Name of the only configured node among the names of the certificate
+    pub fn identify(&self, names: &[String], fingerprint: &str) -> Result<String, IdentityError> {
+        let mut nodes = names
+            .iter()
+            .filter(|name| self.0.contains_key(*name))
+            .collect::<Vec<_>>();
+        nodes.sort_unstable();
+        nodes.dedup();
+        match nodes.as_slice() {
+            [] => Err(IdentityError::Unknown(names.to_vec())),
+            [node] => {
+                self.check_pinned(node, fingerprint)?;
+                Ok((*node).clone())
+            }
+            _ => Err(IdentityError::Ambiguous(
+                nodes.into_iter().cloned().collect(),
+            )),
+        }
+    }
+
+    fn check_pinned(&self, node: &str, fingerprint: &str) -> Result<(), IdentityError> {
+        match self.0.get(node) {
+            None => Err(IdentityError::Unknown(vec![node.to_string()])),
+            Some(pinned) if !pinned.is_empty() && !pinned.contains(fingerprint) => {
+                Err(IdentityError::NotPinned(node.to_string()))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+pub fn warning(conf: &Config) -> Duration {
+    Duration::days(conf.node.cert_expiry_warning_days.into())
+}
+
+/// Checks the certificates of the nodes connecting to this one
+pub struct ClientVerifier {
+    inner: AllowAnyAuthenticatedClient,
+    revocations: Arc<Revocations>,
+    identities: Arc<Identities>,
+}
+
+impl ClientVerifier {
+    pub fn new(
+        roots: RootCertStore,
+        revocations: Arc<Revocations>,
+        identities: Arc<Identities>,
+    ) -> Self {
+        Self {
+            inner: AllowAnyAuthenticatedClient::new(roots),
+            revocations,
+            identities,
+        }
+    }
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let verified = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        self.revocations.check(end_entity)?;
+        let names = peer_names(&end_entity.0)
+            .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let node = self
+            .identities
+            .identify(&names, &sha256_hex(&end_entity.0))?;
+        self.revocations.seen(&node, end_entity);
+        Ok(verified)
+    }
+}
+
+/// Checks the certificates of the nodes this one connects to
+pub struct ServerVerifier {
+    inner: WebPkiVerifier,
+    revocations: Arc<Revocations>,
+    identities: Arc<Identities>,
+}
+
+impl ServerVerifier {
+    pub fn new(
+        roots: RootCertStore,
+        revocations: Arc<Revocations>,
+        identities: Arc<Identities>,
+    ) -> Self {
+        Self {
+            inner: WebPkiVerifier::new(roots, None),
+            revocations,
+            identities,
+        }
+    }
+}
+
+impl ServerCertVerifier for ServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        self.revocations.check(end_entity)?;
+        let fingerprint = sha256_hex(&end_entity.0);
+        let node = match server_name {
+            // The name was checked against the certificate above, it only has to be a configured node
+            ServerName::DnsName(name) => {
+                self.identities.check_pinned(name.as_ref(), &fingerprint)?;
+                name.as_ref().to_string()
+            }
+            // Only the address was checked, the certificate has to name a configured node
+            _ => {
+                let names = peer_names(&end_entity.0)
+                    .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+                self.identities.identify(&names, &fingerprint)?
+            }
+        };
+        self.revocations.seen(&node, end_entity);
+        Ok(verified)
+    }
+}
+
@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use config::NodeConfig;
+use serde_json::json;
+use server::ca::{Ca, CA_KEY_FILE};
+use tokio_rustls::rustls::{
+    client::{ServerCertVerifier, WebPkiVerifier},
+    server::{AllowAnyAuthenticatedClient, ClientCertVerifier},
+    Certificate, RootCertStore, ServerName,
+};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+fn ca_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gilbert-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn der(pem: &str) -> Certificate {
+    Certificate(
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .unwrap()
+            .remove(0),
+    )
+}
+
+fn dns_names(cert: &Certificate) -> Vec<String> {
+    let (_, parsed) = parse_x509_certificate(&cert.0).unwrap();
+    parsed
+        .subject_alternative_name()
+        .unwrap()
+        .unwrap()
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn issued_certificates_chain_to_the_ca() {
+    let dir = ca_dir("issue");
+    let ca = Ca::init(&dir, "Test CA").unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(&der(ca.cert_pem())).unwrap();
+    let server = WebPkiVerifier::new(roots.clone(), None);
+    let client = AllowAnyAuthenticatedClient::new(roots);
+    let now = std::time::SystemTime::now();
+
+    // A reloaded CA keeps issuing certificates the nodes accept
+    for ca in [ca, Ca::load(&dir).unwrap()] {
+        let cert = der(&ca.issue("node1").unwrap().0);
+        assert_eq!(dns_names(&cert), ["node1"]);
+        let verify = |name: &str| {
+            server.verify_server_cert(
+                &cert,
+                &[],
+                &ServerName::try_from(name).unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                now,
+            )
+        };
+        assert!(verify("node1").is_ok());
+        assert!(verify("node2").is_err());
+        assert!(client.verify_client_cert(&cert, &[], now).is_ok());
+    }
+
+    let other_dir = ca_dir("other");
+    let other = Ca::init(&other_dir, "Other CA").unwrap();
+    let cert = der(&other.issue("node1").unwrap().0);
+    assert!(client.verify_client_cert(&cert, &[], now).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&other_dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn private_keys_only_readable_by_owner() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let dir = ca_dir("permissions");
+    let ca = Ca::init(&dir.join("ca"), "Test CA").unwrap();
+    assert_eq!(mode(&dir.join("ca").join(CA_KEY_FILE)), 0o600);
+
+    let node: NodeConfig = serde_json::from_value(json!({
+        "ca_file": dir.join("node/ca.crt"),
+        "cert_file": dir.join("node/node1.crt"),
+        "key_file": dir.join("node/node1.key"),
+        "listen": ["127.0.0.1:1"],
+        "name": "node1"
+    }))
+    .unwrap();
+    // A key left over from before is restricted too
+    std::fs::create_dir_all(dir.join("node")).unwrap();
+    std::fs::write(&node.key_file, "old key").unwrap();
+    std::fs::set_permissions(&node.key_file, std::fs::Permissions::from_mode(0o644)).unwrap();
+    ca.install(&node).unwrap();
+    assert_eq!(mode(&node.key_file), 0o600);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use server::{
+    ca::{self, Ca},
+    enroll::{consume_token, create_token, restore_token, EnrollError},
+};
+use tokio_rustls::rustls::{
+    client::{ServerCertVerifier, WebPkiVerifier},
+    Certificate, RootCertStore, ServerName,
+};
+
+fn ca_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gilbert-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn der(pem: &str) -> Certificate {
+    Certificate(rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0))
+}
+
+#[test]
+fn single_use_tokens() {
+    let dir = ca_dir("tokens");
+    let ca = Ca::init(&dir, "Test CA").unwrap();
+    let token = create_token(&dir).unwrap();
+    let (secret, fingerprint) = token.split_once('.').unwrap();
+    assert_eq!(ca::fingerprint(ca.cert_pem()).unwrap(), fingerprint);
+
+    let other = create_token(&dir).unwrap();
+    consume_token(&dir, secret).unwrap();
+    assert!(matches!(
+        consume_token(&dir, secret),
+        Err(EnrollError::InvalidToken)
+    ));
+    assert!(matches!(
+        consume_token(&dir, "0123"),
+        Err(EnrollError::InvalidToken)
+    ));
+    consume_token(&dir, other.split_once('.').unwrap().0).unwrap();
+
+    // A token given back after a failed join can be used again, once
+    restore_token(&dir, secret).unwrap();
+    consume_token(&dir, secret).unwrap();
+    assert!(matches!(
+        consume_token(&dir, secret),
+        Err(EnrollError::InvalidToken)
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn signed_request() {
+    let dir = ca_dir("sign");
+    let ca = Ca::init(&dir, "Test CA").unwrap();
+    let (csr, _key) = ca::request("node1.example.com").unwrap();
+    // Whatever the request asks for, the certificate is for the node it's signed for
+    let cert = der(&ca.sign_request(&csr, "node2.example.com").unwrap());
+
+    let mut roots = RootCertStore::empty();
+    roots.add(&der(ca.cert_pem())).unwrap();
+    let verifier = WebPkiVerifier::new(roots, None);
+    let verify = |name: &str| {
+        verifier.verify_server_cert(
+            &cert,
+            &[],
+            &ServerName::try_from(name).unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
+    assert!(verify("node2.example.com").is_ok());
+    assert!(verify("node1.example.com").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,79 @@
+use std::{net::TcpListener, path::Path, time::Duration};
+
+use config::Config;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use server::ca::Ca;
+
+/// Config of a cluster of one node, serving on a free port
+fn single_node(dir: &Path) -> (Config, String) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("http://127.0.0.1:{port}");
+    let config = serde_json::from_value(json!({
+        "general": {
+            "nodes": [{"address": address, "name": "node1"}],
+            "tasks": {
+                "job": {
+                    "params": [{"name": "n", "type": "number"}],
+                    "script": dir.join("job.js")
+                }
+            }
+        },
+        "node": {
+            "ca_file": dir.join("node/ca.crt"),
+            "cert_file": dir.join("node/node1.crt"),
+            "key_file": dir.join("node/node1.key"),
+            "listen": [format!("127.0.0.1:{port}")],
+            "name": "node1",
+            "cache_dir": dir.join("cache"),
+            "lock_file": dir.join("gilbert.lock"),
+            "schedules_file": dir.join("schedules.json")
+        }
+    }))
+    .unwrap();
+    (config, address)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn submit_jobs() {
+    let dir = std::env::temp_dir().join(format!("gilbert-jobs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (config, address) = single_node(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("job.js"), "").unwrap();
+    Ca::init(&dir.join("ca"), "Test CA")
+        .unwrap()
+        .install(&config.node)
+        .unwrap();
+    tokio::spawn(server::start(config));
+
+    let client = reqwest::Client::new();
+    let submit = |task: &str, params: Value| {
+        client
+            .post(format!("{address}/api/jobs/{task}"))
+            .json(&params)
+            .send()
+    };
+    let mut res = submit("job", json!({})).await;
+    for _ in 0..100 {
+        if res.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        res = submit("job", json!({})).await;
+    }
+    assert_eq!(res.unwrap().status(), StatusCode::BAD_REQUEST);
+    let res = submit("missing", json!({"n": 1})).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = submit("job", json!({"n": 1})).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let placed: Value = res.json().await.unwrap();
+    assert_eq!(placed["node"], "node1");
+    assert!(placed["id"].is_string());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
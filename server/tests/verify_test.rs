@@ -0,0 +1,185 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use config::Config;
+use rcgen::{
+    Certificate as RcgenCertificate, CertificateParams, CertificateRevocationList,
+    CertificateRevocationListParams, KeyIdMethod, KeyPair, RevokedCertParams, SerialNumber,
+};
+use serde_json::json;
+use server::{
+    ca::{self, Ca, CA_CERT_FILE, CA_KEY_FILE},
+    verify::{Identities, IdentityError, PeerCerts, Revocations},
+};
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::rustls::{Certificate, CertificateError, Error};
+use x509_parser::parse_x509_certificate;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gilbert-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn der(pem: &str) -> Certificate {
+    Certificate(
+        rustls_pemfile::certs(&mut pem.as_bytes())
+            .unwrap()
+            .remove(0),
+    )
+}
+
+fn config(dir: &Path, denied: &[String], crl_files: &[PathBuf]) -> Config {
+    serde_json::from_value(json!({
+        "general": {
+            "nodes": [
+                {"address": "http://127.0.0.1:1", "name": "node1"},
+                {"address": "http://127.0.0.1:2", "name": "node2"},
+                {"address": "http://127.0.0.1:3", "name": "node3", "fingerprints": ["AB:CD"]}
+            ],
+            "tasks": {},
+            "denied_certificates": denied
+        },
+        "node": {
+            "ca_file": dir.join(CA_CERT_FILE),
+            "cert_file": dir.join("node/node1.crt"),
+            "key_file": dir.join("node/node1.key"),
+            "listen": ["127.0.0.1:1"],
+            "name": "node1",
+            "crl_files": crl_files
+        }
+    }))
+    .unwrap()
+}
+
+/// Writes a CRL of the CA in `dir` revoking the certificate, to be updated at `next_update`
+fn revoke(dir: &Path, cert: &Certificate, next_update: OffsetDateTime) -> PathBuf {
+    let key = KeyPair::from_pem(&std::fs::read_to_string(dir.join(CA_KEY_FILE)).unwrap()).unwrap();
+    let pem = std::fs::read_to_string(dir.join(CA_CERT_FILE)).unwrap();
+    let ca = RcgenCertificate::from_params(CertificateParams::from_ca_cert_pem(&pem, key).unwrap())
+        .unwrap();
+    let (_, parsed) = parse_x509_certificate(&cert.0).unwrap();
+    let now = OffsetDateTime::now_utc();
+    let crl = CertificateRevocationList::from_params(CertificateRevocationListParams {
+        this_update: next_update - Duration::days(1),
+        next_update,
+        crl_number: SerialNumber::from_slice(&[1]),
+        issuing_distribution_point: None,
+        revoked_certs: vec![RevokedCertParams {
+            serial_number: SerialNumber::from_slice(parsed.raw_serial()),
+            revocation_time: now,
+            reason_code: None,
+            invalidity_date: None,
+        }],
+        alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+        key_identifier_method: KeyIdMethod::Sha256,
+    })
+    .unwrap();
+    let file = dir.join("ca.crl");
+    std::fs::write(&file, crl.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+    file
+}
+
+fn is_revoked(result: Result<(), Error>) -> bool {
+    matches!(
+        result,
+        Err(Error::InvalidCertificate(CertificateError::Revoked))
+    )
+}
+
+#[test]
+fn denied_and_revoked_certificates() {
+    let dir = temp_dir("revocations");
+    let ca = Ca::init(&dir, "Test CA").unwrap();
+    let (denied, _) = ca.issue("node1").unwrap();
+    let revoked = der(&ca.issue("node2").unwrap().0);
+    let accepted = der(&ca.issue("node3").unwrap().0);
+    let tomorrow = OffsetDateTime::now_utc() + Duration::days(1);
+    let conf = config(
+        &dir,
+        &[ca::fingerprint(&denied).unwrap()],
+        &[revoke(&dir, &revoked, tomorrow)],
+    );
+
+    let peers = Arc::new(PeerCerts::default());
+    let revocations = Revocations::load(&conf, peers.clone()).unwrap();
+    assert!(is_revoked(revocations.check(&der(&denied))));
+    assert!(is_revoked(revocations.check(&revoked)));
+    revocations.check(&accepted).unwrap();
+    revocations.seen("node3", &accepted);
+    let seen = peers.all();
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0].name, "node3");
+
+    assert_eq!(peers.node(&accepted.0).as_deref(), Some("node3"));
+
+    let mut conf = config(
+        &dir,
+        &[ca::fingerprint(&ca.issue("node3").unwrap().0).unwrap()],
+        &[],
+    );
+    conf.general.nodes[2].fingerprints.clear();
+    let identities = Identities::new(&conf.general);
+    let revocations = Revocations::load(&conf, peers.clone()).unwrap();
+    assert!(peers.reject(&revocations, &identities).is_empty());
+
+    let conf = config(&dir, &[seen[0].fingerprint.clone()], &[]);
+    let revocations = Revocations::load(&conf, peers.clone()).unwrap();
+    assert_eq!(peers.reject(&revocations, &identities), ["node3"]);
+    assert!(peers.all().is_empty());
+    assert!(peers.node(&accepted.0).is_none());
+}
+
+#[test]
+fn crl_signature_and_expiry() {
+    let dir = temp_dir("crl");
+    let other = temp_dir("crl-other");
+    let ca = Ca::init(&dir, "Test CA").unwrap();
+    Ca::init(&other, "Other CA").unwrap();
+    let revoked = der(&ca.issue("node2").unwrap().0);
+    let tomorrow = OffsetDateTime::now_utc() + Duration::days(1);
+    let peers = Arc::new(PeerCerts::default());
+
+    let conf = config(&dir, &[], &[revoke(&other, &revoked, tomorrow)]);
+    let error = Revocations::load(&conf, peers.clone()).err().unwrap();
+    assert!(error.to_string().ends_with("isn't signed by the CA"));
+
+    // An outdated list still applies
+    let yesterday = OffsetDateTime::now_utc() - Duration::days(1);
+    let conf = config(&dir, &[], &[revoke(&dir, &revoked, yesterday)]);
+    let revocations = Revocations::load(&conf, peers).unwrap();
+    assert!(is_revoked(revocations.check(&revoked)));
+}
+
+#[test]
+fn identities() {
+    let conf = config(Path::new("."), &[], &[]);
+    let identities = Identities::new(&conf.general);
+    let names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+        identities
+            .identify(&names(&["node1", "other"]), "0123")
+            .unwrap(),
+        "node1"
+    );
+    assert!(matches!(
+        identities.identify(&names(&["other"]), "0123"),
+        Err(IdentityError::Unknown(names)) if names == ["other"]
+    ));
+    assert!(matches!(
+        identities.identify(&names(&["node2", "node1", "node2"]), "0123"),
+        Err(IdentityError::Ambiguous(nodes)) if nodes == ["node1", "node2"]
+    ));
+    // node3 only accepts its pinned certificate
+    assert!(matches!(
+        identities.identify(&names(&["node3"]), "0123"),
+        Err(IdentityError::NotPinned(node)) if node == "node3"
+    ));
+    assert_eq!(
+        identities.identify(&names(&["node3"]), "abcd").unwrap(),
+        "node3"
+    );
+}
@@ -0,0 +1,279 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Output,
+};
+
+pub use config::sha256_hex;
+use config::{Script, Source};
+use thiserror::Error;
+use tokio::{process::Command, sync::Mutex};
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("git {command} failed: {stderr}")]
+    Git { command: String, stderr: String },
+    #[error("a path inside the repository is required")]
+    MissingPath,
+    #[error("{} is not a relative path without ..", .0.display())]
+    InvalidPath(PathBuf),
+    #[error("{0:?} starts with -, git would take it for an option")]
+    InvalidArgument(String),
+}
+
+/// Fetches sources into a local directory.
+///
+/// Web sources are stored by the hash of their contents, git sources by commit,
+/// so a version that was fetched once is never fetched again.
+pub struct SourceCache {
+    dir: PathBuf,
+    client: reqwest::Client,
+    /// Git doesn't like concurrent operations on the same repository
+    git: Mutex<()>,
+}
+
+/// Hash of the file's contents, as stored in lockfiles
+pub async fn sha256_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    Ok(sha256_hex(&tokio::fs::read(path).await?))
+}
+
+/// Git takes arguments starting with - for options, even where a repository or a revision goes
+fn check_arguments(repo: &str, version: Option<&str>) -> Result<(), FetchError> {
+    match std::iter::once(repo)
+        .chain(version)
+        .find(|arg| arg.starts_with('-'))
+    {
+        Some(arg) => Err(FetchError::InvalidArgument(arg.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn is_commit_id(version: &str) -> bool {
+    version.len() == 40 && version.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn check_relative(path: &Path) -> Result<(), FetchError> {
+    if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        Err(FetchError::InvalidPath(path.to_path_buf()))
+    } else {
+        Ok(())
+    }
+}
+
+async fn git(dir: Option<&Path>, args: &[&str]) -> Result<Output, FetchError> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command.args(args).kill_on_drop(true).output().await?;
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(FetchError::Git {
+            command: args.first().copied().unwrap_or_default().to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+impl SourceCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            client: reqwest::Client::new(),
+            git: Mutex::new(()),
+        }
+    }
+
+    /// Local path of the script, fetching it if needed
+    pub async fn script(&self, script: &Script) -> Result<PathBuf, FetchError> {
+        match script {
+            Script::Path(path) => Ok(path.clone()),
+            Script::Source(source) => self.fetch(source).await,
+        }
+    }
+
+    /// Local path of the source, fetching it if needed
+    pub async fn fetch(&self, source: &Source) -> Result<PathBuf, FetchError> {
+        match source {
+            Source::Fs { path } => Ok(path.clone()),
+            Source::Git {
+                repo,
+                version,
+                path,
+            } => {
+                let path = path.as_ref().ok_or(FetchError::MissingPath)?;
+                check_relative(path)?;
+                let (_, checkout) = self.git_checkout(repo, version.as_deref()).await?;
+                Ok(checkout.join(path))
+            }
+            Source::Web { url } => self.web(url).await,
+        }
+    }
+
+    /// Same source, with git versions replaced by the commit they point to right now
+    pub async fn pin(&self, source: &Source) -> Result<Source, FetchError> {
+        Ok(match source {
+            Source::Git {
+                repo,
+                version,
+                path,
+            } => Source::Git {
+                repo: repo.clone(),
+                version: Some(self.git_checkout(repo, version.as_deref()).await?.0),
+                path: path.clone(),
+            },
+            _ => source.clone(),
+        })
+    }
+
+    /// Downloads the file, falling back to the last downloaded version if the server can't be reached
+    async fn web(&self, url: &Url) -> Result<PathBuf, FetchError> {
+        let web = self.dir.join("web");
+        let index = web.join("index").join(sha256_hex(url.as_str().as_bytes()));
+        let file_name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("script.js")
+            .to_string();
+
+        let contents = match self.download(url).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                return match tokio::fs::read_to_string(&index).await {
+                    Ok(hash) => Ok(web.join(hash.trim()).join(file_name)),
+                    Err(_) => Err(e),
+                };
+            }
+        };
+        let hash = sha256_hex(&contents);
+        let dir = web.join(&hash);
+        let file = dir.join(&file_name);
+        if !tokio::fs::try_exists(&file).await? {
+            tokio::fs::create_dir_all(&dir).await?;
+            let tmp = dir.join(format!(".{file_name}.tmp"));
+            tokio::fs::write(&tmp, &contents).await?;
+            tokio::fs::rename(&tmp, &file).await?;
+        }
+        tokio::fs::create_dir_all(web.join("index")).await?;
+        tokio::fs::write(&index, &hash).await?;
+        Ok(file)
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>, FetchError> {
+        let response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Tags of the git repository, fetching it if needed
+    pub async fn tags(&self, repo: &str) -> Result<Vec<String>, FetchError> {
+        check_arguments(repo, None)?;
+        let _lock = self.git.lock().await;
+        let mirror = self.git_mirror(repo, None).await?;
+        let output = git(Some(&mirror), &["tag", "--list"]).await?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Checks out the version of the repository, the default branch if missing, returning the commit and its directory
+    async fn git_checkout(
+        &self,
+        repo: &str,
+        version: Option<&str>,
+    ) -> Result<(String, PathBuf), FetchError> {
+        check_arguments(repo, version)?;
+        let _lock = self.git.lock().await;
+        let checkouts = self.dir.join("git").join("checkouts");
+
+        // A pinned commit that was already checked out doesn't need the network at all
+        if let Some(commit) = version.filter(|v| is_commit_id(v)) {
+            let commit = commit.to_ascii_lowercase();
+            let checkout = checkouts.join(&commit);
+            if tokio::fs::try_exists(&checkout).await? {
+                return Ok((commit, checkout));
+            }
+        }
+
+        let mirror = self.git_mirror(repo, version).await?;
+        let commit = self.rev_parse(&mirror, version).await?;
+        let checkout = checkouts.join(&commit);
+        if !tokio::fs::try_exists(&checkout).await? {
+            tokio::fs::create_dir_all(&checkouts).await?;
+            let tmp = checkouts.join(format!(".{commit}.tmp"));
+            if tokio::fs::try_exists(&tmp).await? {
+                tokio::fs::remove_dir_all(&tmp).await?;
+            }
+            let tmp_str = tmp.to_string_lossy();
+            git(
+                None,
+                &[
+                    "clone",
+                    "--shared",
+                    "--no-checkout",
+                    "--",
+                    &mirror.to_string_lossy(),
+                    &tmp_str,
+                ],
+            )
+            .await?;
+            git(Some(&tmp), &["checkout", "--detach", &commit]).await?;
+            tokio::fs::rename(&tmp, &checkout).await?;
+        }
+        Ok((commit, checkout))
+    }
+
+    /// Bare clone of the repository, brought up to date. Has to be called with the git lock held
+    async fn git_mirror(&self, repo: &str, version: Option<&str>) -> Result<PathBuf, FetchError> {
+        let repos = self.dir.join("git").join("repos");
+        let mirror = repos.join(sha256_hex(repo.as_bytes()));
+        if tokio::fs::try_exists(&mirror).await? {
+            let fetched = git(
+                Some(&mirror),
+                &[
+                    "fetch",
+                    "--prune",
+                    "origin",
+                    "+refs/heads/*:refs/heads/*",
+                    "+refs/tags/*:refs/tags/*",
+                ],
+            )
+            .await;
+            // Without the network, what was fetched before might still do
+            if let Err(e) = fetched {
+                if self.rev_parse(&mirror, version).await.is_err() {
+                    return Err(e);
+                }
+            }
+        } else {
+            tokio::fs::create_dir_all(&repos).await?;
+            git(
+                None,
+                &["clone", "--bare", "--", repo, &mirror.to_string_lossy()],
+            )
+            .await?;
+        }
+        Ok(mirror)
+    }
+
+    async fn rev_parse(&self, mirror: &Path, version: Option<&str>) -> Result<String, FetchError> {
+        let rev = format!("{}^{{commit}}", version.unwrap_or("HEAD"));
+        let output = git(
+            Some(mirror),
+            &["rev-parse", "--verify", "--end-of-options", &rev],
+        )
+        .await?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
@@ -0,0 +1,65 @@
+use std::{path::Path, process::Command};
+
+use config::Source;
+use source_cache::{FetchError, SourceCache};
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[tokio::test]
+async fn git_checkout_by_commit() {
+    let root = std::env::temp_dir().join(format!("gilbert-source-cache-{}", std::process::id()));
+    let repo = root.join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    git(&repo, &["init", "-q"]);
+    std::fs::write(repo.join("job.js"), "v1").unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "v1"]);
+    let first = git(&repo, &["rev-parse", "HEAD"]);
+    std::fs::write(repo.join("job.js"), "v2").unwrap();
+    git(&repo, &["commit", "-q", "-am", "v2"]);
+
+    let cache = SourceCache::new(root.join("cache"));
+    let source = |version: Option<&str>| Source::Git {
+        repo: repo.to_string_lossy().into_owned(),
+        version: version.map(str::to_string),
+        path: Some("job.js".into()),
+    };
+    let latest = cache.fetch(&source(None)).await.unwrap();
+    let pinned = cache.fetch(&source(Some(&first))).await.unwrap();
+    assert_eq!(std::fs::read_to_string(latest).unwrap(), "v2");
+    assert_eq!(std::fs::read_to_string(&pinned).unwrap(), "v1");
+    assert!(pinned.starts_with(root.join("cache").join("git").join("checkouts").join(&first)));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn option_like_git_values() {
+    let root = std::env::temp_dir().join(format!("gilbert-source-options-{}", std::process::id()));
+    let cache = SourceCache::new(root.join("cache"));
+    let source = |repo: &str, version: Option<&str>| Source::Git {
+        repo: repo.to_string(),
+        version: version.map(str::to_string),
+        path: Some("job.js".into()),
+    };
+    for source in [
+        source("--upload-pack=touch pwned", None),
+        source("https://example.com/repo.git", Some("--output=pwned")),
+    ] {
+        assert!(matches!(
+            cache.fetch(&source).await,
+            Err(FetchError::InvalidArgument(_))
+        ));
+    }
+    assert!(!root.exists());
+}
@@ -25,7 +25,7 @@ impl<N: Node> Balancer<N> {
         if let Some(node) = self
             .nodes
             .iter_mut()
-            .filter(|node| task.can_run(node.id()))
+            .filter(|node| node.accepts_tasks() && task.can_run(node.id()))
             .min_by_key(|node| node.sorting())
         {
             // if a node is available (there are nodes present and it can run on one of them)
@@ -34,6 +34,10 @@ pub trait Node {
     fn queue_length(&self) -> usize;
     fn priority(&self) -> usize;
     fn id(&self) -> Self::Id;
+    /// Nodes that only observe the cluster never get tasks
+    fn accepts_tasks(&self) -> bool {
+        true
+    }
     fn sorting(&self) -> SortingPriority {
         SortingPriority {
             queue_length: self.queue_length(),
@@ -65,4 +69,8 @@ where
     fn id(&self) -> Self::Id {
         (**self).id()
     }
+
+    fn accepts_tasks(&self) -> bool {
+        (**self).accepts_tasks()
+    }
 }
@@ -17,6 +17,7 @@ struct MockNode {
     queue: Vec<usize>,
     priority: usize,
     id: usize,
+    observer: bool,
 }
 
 impl MockNode {
@@ -25,6 +26,7 @@ impl MockNode {
             queue: Default::default(),
             priority,
             id,
+            observer: false,
         }
     }
 }
@@ -48,6 +50,10 @@ impl Node for MockNode {
     fn id(&self) -> Self::Id {
         self.id
     }
+
+    fn accepts_tasks(&self) -> bool {
+        !self.observer
+    }
 }
 
 #[test]
@@ -101,3 +107,25 @@ fn multi_node() {
     assert_eq!(node_a.queue, vec![0]);
     assert_eq!(node_b.queue, vec![1, 2]);
 }
+
+#[test]
+fn observer_node() {
+    let mut observer = MockNode::new(10, 0);
+    observer.observer = true;
+    let mut worker = MockNode::new(0, 1);
+    let mut balancer = Balancer::new(vec![&mut observer, &mut worker]);
+    assert!(balancer
+        .enqueue(MockTask {
+            can_run: vec![0, 1],
+            id: 0
+        })
+        .is_ok());
+    assert!(balancer
+        .enqueue(MockTask {
+            can_run: vec![0],
+            id: 1
+        })
+        .is_err());
+    assert!(observer.queue.is_empty());
+    assert_eq!(worker.queue, vec![0]);
+}